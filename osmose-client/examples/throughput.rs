@@ -0,0 +1,77 @@
+//! Measures how many verdicts per second an Osmose server delivers
+//!
+//! Usage: throughput [server address] [connections] [seconds] [source] [destination]
+//!                   [pipelined requests]
+//!
+//! With more than one pipelined request, every connection sends that many
+//! requests at a time with the blocking client before reading the replies.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use osmose_client::{AsyncOsmoseClient, OsmoseClient, VerdictQuery};
+use osmose_identifier::Identifier;
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let address = args.get(1).map_or("127.0.0.1:9061", String::as_str)
+        .parse()
+        .expect("Invalid server address");
+    let connections: usize = args.get(2).map_or(Ok(16), |arg| arg.parse())
+        .expect("Invalid number of connections");
+    let seconds: u64 = args.get(3).map_or(Ok(5), |arg| arg.parse())
+        .expect("Invalid duration");
+    let source = Arc::new(Identifier::from_given(
+        args.get(4).map_or("process1", String::as_str), 1));
+    let destination = args.get(5).map_or("process2", String::as_str);
+    let pipelined: usize = args.get(6).map_or(Ok(1), |arg| arg.parse())
+        .expect("Invalid number of pipelined requests");
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let tasks: Vec<_> = (0..connections)
+        .map(|_| if pipelined > 1 {
+            let client = OsmoseClient::from_socket_address(address);
+            let source = source.clone();
+            let destination = Identifier::from_given(destination, 2);
+            tokio::task::spawn_blocking(move || {
+                let queries: Vec<_> = (0..pipelined)
+                    .map(|_| VerdictQuery {
+                        source: &source, destination: &destination, payload: b"payload" })
+                    .collect();
+                let (mut completed, mut failed) = (0u64, 0u64);
+                while Instant::now() < deadline {
+                    match client.ask_for_verdicts_pipelined(&queries) {
+                        Ok(verdicts) => completed += verdicts.len() as u64,
+                        Err(_) => failed += pipelined as u64,
+                    }
+                }
+                (completed, failed)
+            })
+        } else {
+            let mut client = AsyncOsmoseClient::from_socket_address(address);
+            client.set_self_id(Identifier::from_given(destination, 2));
+            let source = source.clone();
+            tokio::spawn(async move {
+                let (mut completed, mut failed) = (0u64, 0u64);
+                while Instant::now() < deadline {
+                    match client.ask_for_verdict(&source, b"payload").await {
+                        Ok(_) => completed += 1,
+                        Err(_) => failed += 1,
+                    }
+                }
+                (completed, failed)
+            })
+        })
+        .collect();
+
+    let (mut completed, mut failed) = (0u64, 0u64);
+    for task in tasks {
+        let (task_completed, task_failed) = task.await.expect("Client task panicked");
+        completed += task_completed;
+        failed += task_failed;
+    }
+    println!(
+        "{} connections: {} verdicts in {} s ({} per second), {} failed",
+        connections, completed, seconds, completed / seconds.max(1), failed);
+}
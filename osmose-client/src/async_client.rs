@@ -0,0 +1,453 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use osmose_generated::generated_proto::osmose::RequestEnvelope;
+use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+use osmose_identifier::Identifier;
+use osmose_framing::{FrameError, read_message_async, write_message_async, DEFAULT_MAX_FRAME_SIZE};
+
+use crate::{
+    batch_verdicts, prepare_batch, prepare_single, single_verdict,
+    ClientError, ServerAddress, Verdict, VerdictQuery,
+};
+use crate::transport::{AsyncStream, TlsConfig};
+
+
+/// Default time allowed for a single verdict request, including connection
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// An asynchronous client for making verdict requests to Osmose server
+///
+/// Like `OsmoseClient`, it reuses a connection to the server between
+/// requests. The connection is taken out of the client for the duration of
+/// every request and given back only once a matching reply was read, so a
+/// request future which is dropped or times out never leaves a stale reply
+/// behind for the next caller. Concurrent requests open extra connections
+/// instead of waiting for each other.
+pub struct AsyncOsmoseClient {
+    server_address: ServerAddress,
+    self_id: Identifier,
+    max_frame_size: usize,
+    explain: bool,
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+    idle_connection: Mutex<Option<AsyncStream>>,
+    next_request_id: AtomicU64
+}
+
+
+impl Default for AsyncOsmoseClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl AsyncOsmoseClient {
+    /// Returns a default-initialized AsyncOsmoseClient instance
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use osmose_client::AsyncOsmoseClient;
+    /// let client = AsyncOsmoseClient::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::from_socket_address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9061))
+    }
+
+    /// Returns an AsyncOsmoseClient instance with custom server address and
+    /// port
+    ///
+    /// # Arguments
+    ///
+    /// * `ip` - An IpAddr object with IP address of Osmose server
+    /// * `port` - Port for communication with Osmose server
+    pub fn from_address(ip: IpAddr, port: u16) -> Self {
+        Self::from_socket_address(SocketAddr::new(ip, port))
+    }
+
+    /// Returns an AsyncOsmoseClient instance with custom server address and
+    /// port given as a SocketAddr object
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - A SocketAddr object with IP address and port of Osmose
+    ///   server
+    pub fn from_socket_address(address: SocketAddr) -> Self {
+        Self::from_server_address(ServerAddress::Tcp(address))
+    }
+
+    /// Returns an AsyncOsmoseClient instance which talks to Osmose server
+    /// over a Unix domain socket
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the socket Osmose server listens on
+    #[cfg(unix)]
+    pub fn from_unix_socket<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self::from_server_address(ServerAddress::Unix(path.into()))
+    }
+
+    fn from_server_address(address: ServerAddress) -> Self {
+        AsyncOsmoseClient {
+            server_address: address,
+            self_id: Identifier::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            explain: false,
+            timeout: DEFAULT_TIMEOUT,
+            tls: None,
+            idle_connection: Mutex::new(None),
+            next_request_id: AtomicU64::new(1)
+        }
+    }
+
+    /// Sets self ID for this client instance
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier which represents the calling entity
+    pub fn set_self_id(&mut self, id: Identifier) {
+        self.self_id = id;
+    }
+
+    /// Returns self ID for this client instance
+    pub fn get_self_id(&self) -> &Identifier {
+        &self.self_id
+    }
+
+    /// Sets the maximum size of a single frame exchanged with Osmose server
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Maximum frame body size in bytes
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size;
+    }
+
+    /// Returns the maximum size of a single frame for this client instance
+    pub fn get_max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Asks Osmose server to record how each decision was reached
+    ///
+    /// # Arguments
+    ///
+    /// * `explain` - Whether the evaluation trace should be requested
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+    }
+
+    /// Sets the time allowed for a single request, including connecting to
+    /// the server
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Maximum duration of a request
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use osmose_client::AsyncOsmoseClient;
+    ///
+    /// let mut client = AsyncOsmoseClient::new();
+    /// client.set_timeout(Duration::from_millis(200));
+    /// assert_eq!(client.get_timeout(), Duration::from_millis(200));
+    /// ```
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the time allowed for a single request
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Talks to Osmose server over TLS when connected through TCP
+    ///
+    /// # Arguments
+    ///
+    /// * `tls` - TLS settings for the server connection
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.tls = Some(tls);
+        *self.lock_idle_connection() = None;
+    }
+
+    /// Sends a request to Osmose server to get the verdict
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Identifier which represents the calling entity
+    /// * `payload` - Raw message data from the calling entity
+    ///
+    /// # Examples
+    /// ```
+    /// use osmose_identifier::Identifier;
+    /// use osmose_client::AsyncOsmoseClient;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let client = AsyncOsmoseClient::new();
+    ///
+    /// let identifier = Identifier::new();
+    /// let msg = b"test";
+    ///
+    /// match client.ask_for_verdict(&identifier, msg).await {
+    ///     Ok(verdict) => println!("Allowed: {}", verdict.is_allowed()),
+    ///     Err(e) => println!("Cannot get verdict: {}", e),
+    /// }
+    /// # }
+    /// ```
+    pub async fn ask_for_verdict(
+        &self, source: &Identifier, payload: &[u8]
+    ) -> Result<Verdict, ClientError> {
+        let request_id = self.next_request_id();
+        let envelope = prepare_single(
+            source, self.get_self_id(), payload, None, request_id, self.explain);
+        self.send_single(&envelope, request_id).await
+    }
+
+    /// Sends a request with a payload of a declared type to Osmose server
+    ///
+    /// The payload is sent as `google.protobuf.Any`, so the server may
+    /// decide by its type alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Identifier which represents the calling entity
+    /// * `type_url` - Type URL of the message, e.g.
+    ///   `type.googleapis.com/payments.Refund`
+    /// * `payload` - Serialized message from the calling entity
+    pub async fn ask_for_typed_verdict(
+        &self, source: &Identifier, type_url: &str, payload: &[u8]
+    ) -> Result<Verdict, ClientError> {
+        let request_id = self.next_request_id();
+        let envelope = prepare_single(
+            source, self.get_self_id(), payload, Some(type_url), request_id, self.explain);
+        self.send_single(&envelope, request_id).await
+    }
+
+    async fn send_single(
+        &self, envelope: &RequestEnvelope, request_id: u64
+    ) -> Result<Verdict, ClientError> {
+        let (response, stream) = self.send(envelope).await?;
+        let verdict = single_verdict(response, request_id)?;
+        self.release(stream);
+        Ok(verdict)
+    }
+
+    /// Sends a batch of requests to Osmose server in a single round trip
+    /// Returns a verdict for every query, in the same order as the queries
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - Source/destination pairs with their payloads
+    pub async fn ask_for_verdicts(
+        &self, queries: &[VerdictQuery<'_>]
+    ) -> Result<Vec<Verdict>, ClientError> {
+        let batch = prepare_batch(queries, self.explain, || self.next_request_id());
+
+        let (response, stream) = self.send(&batch.envelope).await?;
+        let verdicts = batch_verdicts(response, &batch)?;
+        self.release(stream);
+        Ok(verdicts)
+    }
+
+    fn next_request_id(&self) -> u64 {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn lock_idle_connection(&self) -> MutexGuard<'_, Option<AsyncStream>> {
+        self.idle_connection.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Keeps the connection for the next request unless another one is
+    /// already kept
+    fn release(&self, stream: AsyncStream) {
+        let mut idle = self.lock_idle_connection();
+        if idle.is_none() {
+            *idle = Some(stream);
+        }
+    }
+
+    /// Sends the request and waits for the reply within the configured
+    /// timeout, returning the reply together with the connection it came on
+    async fn send(
+        &self, request: &RequestEnvelope
+    ) -> Result<(ResponseEnvelope, AsyncStream), ClientError> {
+        match tokio::time::timeout(self.timeout, self.round_trip(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(ClientError::Timeout(self.timeout)),
+        }
+    }
+
+    /// Sends the request over the idle connection, if there is one, and
+    /// falls back to a new connection if the idle one turns out to be closed
+    async fn round_trip(
+        &self, request: &RequestEnvelope
+    ) -> Result<(ResponseEnvelope, AsyncStream), ClientError> {
+        let idle = self.lock_idle_connection().take();
+        if let Some(mut stream) = idle {
+            match exchange(&mut stream, request, self.max_frame_size).await {
+                Ok(response) => return Ok((response, stream)),
+                Err(FrameError::Io(e)) => {
+                    log::debug!(
+                        "Cached connection to OSMOSE server failed: {}", e);
+                },
+                Err(FrameError::Closed) => {
+                    log::debug!("Cached connection to OSMOSE server closed");
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut stream = self.server_address.connect_async(self.tls.as_ref()).await
+            .map_err(ClientError::Connect)?;
+        log::debug!("Connected to OSMOSE server {}", &self.server_address);
+
+        let response = exchange(&mut stream, request, self.max_frame_size).await?;
+        Ok((response, stream))
+    }
+}
+
+
+/// Sends a single request and reads a single response from the stream
+async fn exchange(
+    stream: &mut AsyncStream, request: &RequestEnvelope, max_frame_size: usize
+) -> Result<ResponseEnvelope, FrameError> {
+    write_message_async(stream, request, max_frame_size).await?;
+    log::debug!("Sent request: {:?}", request);
+
+    let response: ResponseEnvelope = read_message_async(stream, max_frame_size).await?;
+    log::debug!("Received reply: {:?}", &response);
+    Ok(response)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+    use osmose_generated::generated_proto::osmose::RequestEnvelope;
+    use osmose_generated::generated_proto::osmose::RequestEnvelope_oneof_body as RequestBody;
+    use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+    use osmose_framing::{
+        read_message_async, write_message_async, FrameError, DEFAULT_MAX_FRAME_SIZE,
+    };
+    use osmose_identifier::Identifier;
+    use crate::{AsyncOsmoseClient, ClientError, Decision};
+
+    async fn listen() -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Cannot bind test server");
+        let address = listener.local_addr().unwrap();
+        (listener, address)
+    }
+
+    /// Reads a single request, returning its identifier
+    async fn read_request(stream: &mut TcpStream) -> Result<u64, FrameError> {
+        let envelope: RequestEnvelope = read_message_async(stream, DEFAULT_MAX_FRAME_SIZE).await?;
+        match envelope.body {
+            Some(RequestBody::single(request)) => Ok(request.get_request_id()),
+            other => panic!("Unexpected request: {:?}", other),
+        }
+    }
+
+    /// Allows the request, giving its identifier as the reason
+    async fn reply(stream: &mut TcpStream, request_id: u64) -> Result<(), FrameError> {
+        let mut response = Response::new();
+        response.set_request_id(request_id);
+        response.set_decision(Decision::ALLOW);
+        response.set_reason(format!("request {}", request_id));
+        let mut envelope = ResponseEnvelope::new();
+        envelope.set_single(response);
+        write_message_async(stream, &envelope, DEFAULT_MAX_FRAME_SIZE).await
+    }
+
+    async fn ask(client: &AsyncOsmoseClient) -> Result<String, ClientError> {
+        let verdict = client.ask_for_verdict(&Identifier::from_given("process1", 1), b"test")
+            .await?;
+        assert!(verdict.is_allowed());
+        Ok(verdict.get_reason().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_reply_in_time() {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for _ in 0..2 {
+                let request_id = read_request(&mut stream).await.unwrap();
+                reply(&mut stream, request_id).await.unwrap();
+            }
+        });
+        let mut client = AsyncOsmoseClient::from_socket_address(address);
+        client.set_timeout(Duration::from_secs(5));
+        assert_eq!(ask(&client).await.unwrap(), "request 1");
+        assert_eq!(ask(&client).await.unwrap(), "request 2");
+        server.await.expect("Both requests should come over one connection");
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            // The first request is never answered
+            let (mut stream, _) = listener.accept().await.unwrap();
+            assert_eq!(read_request(&mut stream).await.unwrap(), 1);
+            match read_request(&mut stream).await {
+                Err(FrameError::Closed) => (),
+                other => panic!("Connection should be dropped, got {:?}", other),
+            }
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request_id = read_request(&mut stream).await.unwrap();
+            reply(&mut stream, request_id).await.unwrap();
+        });
+        let mut client = AsyncOsmoseClient::from_socket_address(address);
+        client.set_timeout(Duration::from_millis(100));
+        match ask(&client).await {
+            Err(ClientError::Timeout(timeout)) => assert_eq!(timeout, Duration::from_millis(100)),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        client.set_timeout(Duration::from_secs(5));
+        assert_eq!(ask(&client).await.unwrap(), "request 2");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled() {
+        let (listener, address) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request_id = read_request(&mut stream).await.unwrap();
+            reply(&mut stream, request_id).await.unwrap();
+            // The reply to the cancelled request comes too late for anyone
+            // to read it
+            let request_id = read_request(&mut stream).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let _ = reply(&mut stream, request_id).await;
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request_id = read_request(&mut stream).await.unwrap();
+            reply(&mut stream, request_id).await.unwrap();
+        });
+        let client = AsyncOsmoseClient::from_socket_address(address);
+        assert_eq!(ask(&client).await.unwrap(), "request 1");
+        assert!(tokio::time::timeout(Duration::from_millis(50), ask(&client)).await.is_err());
+        assert_eq!(ask(&client).await.unwrap(), "request 3");
+        server.await.unwrap();
+    }
+}
+
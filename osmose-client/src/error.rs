@@ -0,0 +1,133 @@
+use std::fmt;
+
+use osmose_framing::FrameError;
+
+use crate::Verdict;
+
+
+/// Errors which may prevent Osmose client from getting a verdict
+#[derive(Debug)]
+pub enum ClientError {
+    /// Osmose server cannot be reached
+    Connect(std::io::Error),
+    /// Connection failed while the request was in flight
+    Io(std::io::Error),
+    /// Osmose server closed the connection before replying
+    ConnectionClosed,
+    /// Request or reply does not fit into the maximum frame size
+    FrameTooLarge { size: u64, max: usize },
+    /// Reply cannot be decoded
+    MalformedResponse(String),
+    /// Reply does not correspond to the request which was sent
+    UnexpectedResponse(String),
+    /// Osmose server refused to evaluate the request as a whole
+    Rejected(Verdict),
+    /// No reply was received in time
+    Timeout(std::time::Duration),
+}
+
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Connect(e) => write!(
+                f, "cannot connect to Osmose server: {}", e),
+            ClientError::Io(e) => write!(f, "connection error: {}", e),
+            ClientError::ConnectionClosed => write!(
+                f, "connection closed by Osmose server"),
+            ClientError::FrameTooLarge { size, max } => write!(
+                f, "frame of {} bytes exceeds maximum frame size of {} bytes",
+                size, max),
+            ClientError::MalformedResponse(e) => write!(
+                f, "malformed reply: {}", e),
+            ClientError::UnexpectedResponse(e) => write!(
+                f, "unexpected reply: {}", e),
+            ClientError::Rejected(verdict) => write!(
+                f, "request rejected with {:?}: {}",
+                verdict.get_decision(), verdict.get_reason()),
+            ClientError::Timeout(timeout) => write!(
+                f, "no reply from Osmose server within {:?}", timeout),
+        }
+    }
+}
+
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<FrameError> for ClientError {
+    fn from(error: FrameError) -> Self {
+        match error {
+            FrameError::Io(e) => ClientError::Io(e),
+            FrameError::Closed => ClientError::ConnectionClosed,
+            FrameError::TooLarge { size, max } => {
+                ClientError::FrameTooLarge { size, max }
+            },
+            FrameError::InvalidLength => {
+                ClientError::MalformedResponse(error.to_string())
+            },
+            FrameError::Protobuf(e) => {
+                ClientError::MalformedResponse(e.to_string())
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use osmose_framing::FrameError;
+    use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+    use osmose_generated::generated_proto::osmose::Decision as Decision;
+    use crate::{ClientError, Verdict};
+
+    #[test]
+    fn test_from_frame_error() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        match ClientError::from(FrameError::Io(io)) {
+            ClientError::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+        match ClientError::from(FrameError::Closed) {
+            ClientError::ConnectionClosed => (),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+        match ClientError::from(FrameError::TooLarge { size: 16, max: 8 }) {
+            ClientError::FrameTooLarge { size: 16, max: 8 } => (),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+        match ClientError::from(FrameError::InvalidLength) {
+            ClientError::MalformedResponse(e) => assert_eq!(e, "invalid frame length prefix"),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let error = ClientError::Connect(io);
+        assert_eq!(error.to_string(), "cannot connect to Osmose server: refused");
+        assert!(error.source().is_some());
+
+        let mut response = Response::new();
+        response.set_decision(Decision::MALFORMED_MESSAGE);
+        response.set_reason("empty request envelope".to_owned());
+        let error = ClientError::Rejected(Verdict::from(&response));
+        assert_eq!(
+            error.to_string(), "request rejected with MALFORMED_MESSAGE: empty request envelope");
+        assert!(error.source().is_none());
+
+        let error = ClientError::Timeout(std::time::Duration::from_millis(200));
+        assert_eq!(error.to_string(), "no reply from Osmose server within 200ms");
+    }
+}
+
@@ -1,18 +1,78 @@
-use std::net::{TcpStream};
+mod error;
+mod transport;
+mod verdict;
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "tls")]
+mod tls;
+
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use osmose_generated::generated_proto::osmose::Identifier as InternalIdentifier;
 use osmose_generated::generated_proto::osmose::DecisionRequest as Request;
-use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
-use osmose_generated::generated_proto::osmose::Decision as Decision;
+use osmose_generated::generated_proto::osmose::DecisionBatchRequest as BatchRequest;
+use osmose_generated::generated_proto::osmose::RequestEnvelope;
+use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+use osmose_generated::generated_proto::osmose::ResponseEnvelope_oneof_body as ResponseBody;
 use osmose_identifier::Identifier;
-use protobuf::Message;
+use protobuf::well_known_types::Any;
+use crate::transport::Stream;
+#[cfg(not(feature = "tls"))]
+use crate::transport::TlsConfig;
+
+use osmose_framing::{FrameError, read_message, write_message, DEFAULT_MAX_FRAME_SIZE};
+
+
+/// Number of pipelined requests sent ahead of the replies read so far, which
+/// keeps the replies from filling up the socket buffers while requests are
+/// still being written
+const PIPELINE_DEPTH: usize = 32;
+
+pub use osmose_generated::generated_proto::osmose::Decision;
+pub use crate::error::ClientError;
+pub use crate::transport::ServerAddress;
+pub use crate::verdict::Verdict;
+#[cfg(feature = "async")]
+pub use crate::async_client::AsyncOsmoseClient;
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsConfig;
 
 
 /// A client for making verdict requests to Osmose server
+///
+/// The client keeps a single connection to the server open and reuses it
+/// for consecutive requests, reconnecting transparently if it was dropped.
+/// Requests made from several threads take turns on the connection, while
+/// `ask_for_verdicts_pipelined` sends several requests without waiting for
+/// the reply to each of them in turn.
 pub struct OsmoseClient {
-    server_address: SocketAddr,
-    self_id: Identifier
+    server_address: ServerAddress,
+    self_id: Identifier,
+    max_frame_size: usize,
+    explain: bool,
+    tls: Option<TlsConfig>,
+    connection: Mutex<Option<Stream>>,
+    next_request_id: AtomicU64
+}
+
+
+/// A single source/destination pair to be checked as part of a batch
+pub struct VerdictQuery<'a> {
+    /// Identifier of the calling entity
+    pub source: &'a Identifier,
+    /// Identifier of the entity which receives the message
+    pub destination: &'a Identifier,
+    /// Raw message data from the calling entity
+    pub payload: &'a [u8],
+}
+
+
+impl Default for OsmoseClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 
@@ -26,11 +86,8 @@ impl OsmoseClient {
     /// let client = OsmoseClient::new();
     /// ```
     pub fn new() -> Self {
-        OsmoseClient{
-            server_address: SocketAddr::new(IpAddr::V4(
-                                    Ipv4Addr::new(127, 0, 0, 1)), 9061),
-            self_id: Identifier::new()
-        }
+        Self::from_socket_address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9061))
     }
 
     /// Returns an OsmoseClient instance with custom server address and port
@@ -51,10 +108,7 @@ impl OsmoseClient {
     /// let client = OsmoseClient::from_address(server_addr, server_port);
     /// ```
     pub fn from_address(ip: IpAddr, port: u16) -> Self {
-        OsmoseClient{ 
-            server_address: SocketAddr::new(ip, port),
-            self_id: Identifier::new()
-        }
+        Self::from_socket_address(SocketAddr::new(ip, port))
     }
 
     /// Returns an OsmoseClient instance with custom server address and port
@@ -63,7 +117,7 @@ impl OsmoseClient {
     /// # Arguments
     ///
     /// * `address` - A SocketAddr object with IP address and port of Osmose
-    /// server
+    ///   server
     ///
     /// # Examples
     ///
@@ -77,9 +131,37 @@ impl OsmoseClient {
     /// let client = OsmoseClient::from_socket_address(server_sockaddr);
     /// ```
     pub fn from_socket_address(address: SocketAddr) -> Self {
-        OsmoseClient{ 
+        Self::from_server_address(ServerAddress::Tcp(address))
+    }
+
+    /// Returns an OsmoseClient instance which talks to Osmose server over
+    /// a Unix domain socket
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the socket Osmose server listens on
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use osmose_client::OsmoseClient;
+    ///
+    /// let client = OsmoseClient::from_unix_socket("/run/osmose.sock");
+    /// ```
+    #[cfg(unix)]
+    pub fn from_unix_socket<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self::from_server_address(ServerAddress::Unix(path.into()))
+    }
+
+    fn from_server_address(address: ServerAddress) -> Self {
+        OsmoseClient {
             server_address: address,
-            self_id: Identifier::new()
+            self_id: Identifier::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            explain: false,
+            tls: None,
+            connection: Mutex::new(None),
+            next_request_id: AtomicU64::new(1)
         }
     }
 
@@ -119,9 +201,71 @@ impl OsmoseClient {
         &self.self_id
     }
 
+    /// Sets the maximum size of a single frame exchanged with Osmose server
+    ///
+    /// Requests larger than this are not sent, and responses larger than
+    /// this are rejected without being read.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Maximum frame body size in bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use osmose_client::OsmoseClient;
+    ///
+    /// let mut client = OsmoseClient::new();
+    /// client.set_max_frame_size(64 * 1024);
+    /// assert_eq!(client.get_max_frame_size(), 64 * 1024);
+    /// ```
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size;
+    }
+
+    /// Returns the maximum size of a single frame for this client instance
+    pub fn get_max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Asks Osmose server to record how each decision was reached
+    ///
+    /// The evaluation trace is then available from `Verdict::get_trace`.
+    ///
+    /// # Arguments
+    ///
+    /// * `explain` - Whether the evaluation trace should be requested
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use osmose_client::OsmoseClient;
+    ///
+    /// let mut client = OsmoseClient::new();
+    /// client.set_explain(true);
+    /// ```
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+    }
+
+    /// Talks to Osmose server over TLS when connected through TCP
+    ///
+    /// A connection opened before is dropped, so the next request is sent
+    /// over TLS.
+    ///
+    /// # Arguments
+    ///
+    /// * `tls` - TLS settings for the server connection
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.tls = Some(tls);
+        self.disconnect();
+    }
+
     /// Sends a request to Osmose server to get the verdict
-    /// Returns `true` if Osmose server allows to pass the given message from
-    /// the calling entity to the current entity, false otherwise
+    /// Returns the decision of Osmose server on passing the given message from
+    /// the calling entity to the current entity, or an error if the decision
+    /// could not be obtained
     ///
     /// # Arguments
     ///
@@ -131,42 +275,337 @@ impl OsmoseClient {
     /// # Examples
     /// ```
     /// use osmose_identifier::Identifier;
+    /// use osmose_client::{ClientError, OsmoseClient};
+    ///
+    /// let client = OsmoseClient::new();
+    ///
+    /// let identifier = Identifier::new();
+    /// let msg = b"test";
+    ///
+    /// match client.ask_for_verdict(&identifier, msg) {
+    ///     Ok(verdict) => println!("Allowed: {}", verdict.is_allowed()),
+    ///     Err(ClientError::Connect(e)) => println!("Server is unreachable: {}", e),
+    ///     Err(e) => println!("Cannot get verdict: {}", e),
+    /// }
+    /// ```
+    pub fn ask_for_verdict(
+        &self, source: &Identifier, payload: &[u8]
+    ) -> Result<Verdict, ClientError> {
+        let request_id = self.next_request_id();
+        let envelope = prepare_single(
+            source, self.get_self_id(), payload, None, request_id, self.explain);
+        self.send_single(&envelope, request_id)
+    }
+
+    /// Sends a request with a payload of a declared type to Osmose server
+    /// Returns the decision of Osmose server, which may allow or refuse the
+    /// call by the type alone
+    ///
+    /// The payload is sent as `google.protobuf.Any`.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Identifier which represents the calling entity
+    /// * `type_url` - Type URL of the message, e.g.
+    ///   `type.googleapis.com/payments.Refund`
+    /// * `payload` - Serialized message from the calling entity
+    ///
+    /// # Examples
+    /// ```
+    /// use osmose_identifier::Identifier;
     /// use osmose_client::OsmoseClient;
-    /// use osmose_generated::generated_proto::osmose::Decision as Decision;
     ///
     /// let client = OsmoseClient::new();
     ///
     /// let identifier = Identifier::new();
+    /// let refund = b"\x10\x64";
+    ///
+    /// match client.ask_for_typed_verdict(
+    ///         &identifier, "type.googleapis.com/payments.Refund", refund) {
+    ///     Ok(verdict) => println!("Allowed: {}", verdict.is_allowed()),
+    ///     Err(e) => println!("Cannot get verdict: {}", e),
+    /// }
+    /// ```
+    pub fn ask_for_typed_verdict(
+        &self, source: &Identifier, type_url: &str, payload: &[u8]
+    ) -> Result<Verdict, ClientError> {
+        let request_id = self.next_request_id();
+        let envelope = prepare_single(
+            source, self.get_self_id(), payload, Some(type_url), request_id, self.explain);
+        self.send_single(&envelope, request_id)
+    }
+
+    fn send_single(
+        &self, envelope: &RequestEnvelope, request_id: u64
+    ) -> Result<Verdict, ClientError> {
+        let response = self.send(std::slice::from_ref(envelope))?.remove(0);
+        let result = single_verdict(response, request_id);
+        if let Err(ClientError::UnexpectedResponse(_)) = result {
+            self.disconnect();
+        }
+        result
+    }
+
+    /// Sends a batch of requests to Osmose server in a single round trip
+    /// Returns a verdict for every query, in the same order as the queries
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - Source/destination pairs with their payloads
+    ///
+    /// # Examples
+    /// ```
+    /// use osmose_identifier::Identifier;
+    /// use osmose_client::{OsmoseClient, VerdictQuery};
+    ///
+    /// let client = OsmoseClient::new();
+    ///
+    /// let source = Identifier::from_given("broker", 1);
+    /// let destinations = vec![
+    ///     Identifier::from_given("consumer1", 2),
+    ///     Identifier::from_given("consumer2", 3),
+    /// ];
     /// let msg = b"test";
     ///
-    /// let verdict = client.ask_for_verdict(&identifier, msg);
-    /// assert_eq!(verdict, false);
+    /// let queries: Vec<_> = destinations.iter()
+    ///     .map(|destination| VerdictQuery {
+    ///         source: &source, destination, payload: msg })
+    ///     .collect();
+    /// if let Ok(verdicts) = client.ask_for_verdicts(&queries) {
+    ///     assert_eq!(verdicts.len(), queries.len());
+    /// }
+    /// ```
+    pub fn ask_for_verdicts(
+        &self, queries: &[VerdictQuery]
+    ) -> Result<Vec<Verdict>, ClientError> {
+        let batch = prepare_batch(queries, self.explain, || self.next_request_id());
+
+        let response = self.send(std::slice::from_ref(&batch.envelope))?.remove(0);
+        let result = batch_verdicts(response, &batch);
+        if let Err(ClientError::UnexpectedResponse(_)) = result {
+            self.disconnect();
+        }
+        result
+    }
+
+    /// Sends a request for every query over the connection without waiting
+    /// for each reply before sending the next request
+    /// Returns a verdict for every query, in the same order as the queries
+    ///
+    /// Unlike a batch, every query is a request of its own, so the server
+    /// evaluates them one by one and the replies are matched with the
+    /// requests by their identifiers.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - Source/destination pairs with their payloads
+    ///
+    /// # Examples
     /// ```
+    /// use osmose_identifier::Identifier;
+    /// use osmose_client::{OsmoseClient, VerdictQuery};
     ///
-    // TODO: change return type
-    pub fn ask_for_verdict(&self, source: &Identifier, payload: &[u8]) -> bool {
-        let request = prepare_request(source, &self.get_self_id(), payload);
+    /// let client = OsmoseClient::new();
+    ///
+    /// let source = Identifier::from_given("broker", 1);
+    /// let destination = Identifier::from_given("consumer", 2);
+    /// let queries: Vec<_> = [b"first", b"other"].iter()
+    ///     .map(|msg| VerdictQuery { source: &source, destination: &destination, payload: *msg })
+    ///     .collect();
+    /// if let Ok(verdicts) = client.ask_for_verdicts_pipelined(&queries) {
+    ///     assert_eq!(verdicts.len(), queries.len());
+    /// }
+    /// ```
+    pub fn ask_for_verdicts_pipelined(
+        &self, queries: &[VerdictQuery]
+    ) -> Result<Vec<Verdict>, ClientError> {
+        let mut request_ids = Vec::with_capacity(queries.len());
+        let envelopes: Vec<_> = queries.iter()
+            .map(|query| {
+                let request_id = self.next_request_id();
+                request_ids.push(request_id);
+                prepare_single(
+                    query.source, query.destination, query.payload, None,
+                    request_id, self.explain)
+            })
+            .collect();
 
-        match TcpStream::connect(&self.server_address) {
-            Ok(mut stream) => {
-                log::debug!(
-                    "Connected to OSMOSE server {}", &self.server_address);
+        let result = self.send(&envelopes)?.into_iter()
+            .zip(request_ids)
+            .map(|(response, request_id)| single_verdict(response, request_id))
+            .collect();
+        if let Err(ClientError::UnexpectedResponse(_)) = result {
+            self.disconnect();
+        }
+        result
+    }
 
-                request.write_to_writer(&mut stream).unwrap();
-                log::debug!("Sent request: {:?}", &request);
+    fn next_request_id(&self) -> u64 {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
 
-                let response = Response::parse_from_reader(&mut stream).unwrap();
-                log::debug!("Received reply: {:?}", &response);
-                match response.get_decision() {
-                    Decision::ALLOW => true,
-                    _ => false
+    fn disconnect(&self) {
+        *self.lock_connection() = None;
+    }
+
+    fn lock_connection(&self) -> MutexGuard<'_, Option<Stream>> {
+        self.connection.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sends the requests over the cached connection and waits for their
+    /// replies, which come in the order of the requests
+    ///
+    /// A connection which turns out to be closed by the server is replaced
+    /// with a new one and the requests are sent once again. The connection is
+    /// dropped on any error so that the next request starts from scratch.
+    fn send(&self, requests: &[RequestEnvelope]) -> Result<Vec<ResponseEnvelope>, ClientError> {
+        let mut connection = self.lock_connection();
+        if let Some(stream) = connection.as_mut() {
+            match exchange(stream, requests, self.max_frame_size) {
+                Ok(response) => return Ok(response),
+                Err(FrameError::Io(e)) => {
+                    log::debug!(
+                        "Cached connection to OSMOSE server failed: {}", e);
+                    *connection = None;
+                },
+                Err(FrameError::Closed) => {
+                    log::debug!("Cached connection to OSMOSE server closed");
+                    *connection = None;
+                },
+                Err(e) => {
+                    *connection = None;
+                    return Err(e.into());
                 }
-            },
-            Err(e) => {
-                log::error!("Failed to connect to Osmose server: {}", e);
-                false
             }
         }
+
+        let stream = self.server_address.connect(self.tls.as_ref())
+            .map_err(ClientError::Connect)?;
+        let stream = connection.insert(stream);
+        log::debug!("Connected to OSMOSE server {}", &self.server_address);
+
+        let result = exchange(stream, requests, self.max_frame_size);
+        if result.is_err() {
+            *connection = None;
+        }
+        Ok(result?)
+    }
+}
+
+
+/// Sends the requests and reads a response to each of them from the stream,
+/// keeping up to `PIPELINE_DEPTH` requests in flight
+fn exchange(
+    stream: &mut Stream, requests: &[RequestEnvelope], max_frame_size: usize
+) -> Result<Vec<ResponseEnvelope>, FrameError> {
+    let mut responses = Vec::with_capacity(requests.len());
+    for (i, request) in requests.iter().enumerate() {
+        if i >= PIPELINE_DEPTH {
+            responses.push(read_response(stream, max_frame_size)?);
+        }
+        write_message(stream, request, max_frame_size)?;
+        log::debug!("Sent request: {:?}", request);
+    }
+    while responses.len() < requests.len() {
+        responses.push(read_response(stream, max_frame_size)?);
+    }
+    Ok(responses)
+}
+
+
+fn read_response(
+    stream: &mut Stream, max_frame_size: usize
+) -> Result<ResponseEnvelope, FrameError> {
+    let response: ResponseEnvelope = read_message(stream, max_frame_size)?;
+    log::debug!("Received reply: {:?}", &response);
+    Ok(response)
+}
+
+
+/// Batch request together with the identifiers needed to check its reply
+struct PreparedBatch {
+    envelope: RequestEnvelope,
+    batch_id: u64,
+    request_ids: Vec<u64>,
+}
+
+
+/// Wraps a single request into an envelope
+fn prepare_single(
+    from: &Identifier, to: &Identifier, msg: &[u8], type_url: Option<&str>,
+    request_id: u64, explain: bool
+) -> RequestEnvelope {
+    let mut envelope = RequestEnvelope::new();
+    envelope.set_single(prepare_request(from, to, msg, type_url, request_id, explain));
+    envelope
+}
+
+
+/// Builds a batch request, taking a fresh identifier for the batch itself
+/// and for each of its requests from `next_request_id`
+fn prepare_batch<F: FnMut() -> u64>(
+    queries: &[VerdictQuery], explain: bool, mut next_request_id: F
+) -> PreparedBatch {
+    let mut batch = BatchRequest::new();
+    batch.set_request_id(next_request_id());
+    for query in queries {
+        batch.mut_requests().push(prepare_request(
+            query.source, query.destination, query.payload, None,
+            next_request_id(), explain));
+    }
+    let request_ids: Vec<u64> = batch.get_requests()
+        .iter()
+        .map(|request| request.get_request_id())
+        .collect();
+    let batch_id = batch.get_request_id();
+
+    let mut envelope = RequestEnvelope::new();
+    envelope.set_batch(batch);
+    PreparedBatch { envelope, batch_id, request_ids }
+}
+
+
+/// Extracts the verdict from the reply to a single request
+fn single_verdict(
+    response: ResponseEnvelope, request_id: u64
+) -> Result<Verdict, ClientError> {
+    match response {
+        ResponseEnvelope { body: Some(ResponseBody::single(response)), .. }
+                if response.get_request_id() == request_id => {
+            Ok(Verdict::from(&response))
+        },
+        // Request id 0 is never assigned, so it marks a reply to a request
+        // which the server could not even parse
+        ResponseEnvelope { body: Some(ResponseBody::single(response)), .. }
+                if response.get_request_id() == 0 => {
+            Err(ClientError::Rejected(Verdict::from(&response)))
+        },
+        response => Err(ClientError::UnexpectedResponse(format!(
+            "{:?} while waiting for request {}", response, request_id)))
+    }
+}
+
+
+/// Extracts the verdicts from the reply to a batch request
+fn batch_verdicts(
+    response: ResponseEnvelope, batch: &PreparedBatch
+) -> Result<Vec<Verdict>, ClientError> {
+    match response {
+        ResponseEnvelope { body: Some(ResponseBody::batch(response)), .. }
+                if response.get_request_id() == batch.batch_id
+                && response.get_responses()
+                    .iter()
+                    .map(|response| response.get_request_id())
+                    .eq(batch.request_ids.iter().copied()) => {
+            Ok(response.get_responses().iter().map(Verdict::from).collect())
+        },
+        ResponseEnvelope { body: Some(ResponseBody::single(response)), .. }
+                if response.get_request_id() == 0 => {
+            Err(ClientError::Rejected(Verdict::from(&response)))
+        },
+        response => Err(ClientError::UnexpectedResponse(format!(
+            "{:?} while waiting for batch {}", response, batch.batch_id)))
     }
 }
 
@@ -177,16 +616,195 @@ impl OsmoseClient {
 ///
 /// * `from` - Identifier of the calling entity
 /// * `to` - Identifier of the target entity, in particular the current one
-/// which actually asks Osmose server for the verdict
+///   which actually asks Osmose server for the verdict
 /// * `msg` - Message from the calling entity
-fn prepare_request(from: &Identifier, to: &Identifier, msg: &[u8]) -> Request {
+/// * `type_url` - Type of the message, which is then sent as
+///   `google.protobuf.Any`
+/// * `request_id` - Identifier used to correlate the request with its reply
+/// * `explain` - Whether the server should record the evaluation trace
+fn prepare_request(
+    from: &Identifier, to: &Identifier, msg: &[u8], type_url: Option<&str>,
+    request_id: u64, explain: bool
+) -> Request {
     let mut req = Request::new();
 
+    req.set_request_id(request_id);
+    req.set_explain(explain);
     req.set_source(InternalIdentifier::from(from));
     req.set_destination(InternalIdentifier::from(to));
 
-    req.set_payload(std::vec::Vec::from(msg));
+    match type_url {
+        Some(type_url) => {
+            let mut typed = Any::new();
+            typed.set_type_url(type_url.to_owned());
+            typed.set_value(std::vec::Vec::from(msg));
+            req.set_typed_payload(typed);
+        },
+        None => req.set_payload(std::vec::Vec::from(msg)),
+    }
 
     req
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+    use osmose_generated::generated_proto::osmose::RequestEnvelope;
+    use osmose_generated::generated_proto::osmose::RequestEnvelope_oneof_body as RequestBody;
+    use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+    use osmose_framing::{read_message, write_message, DEFAULT_MAX_FRAME_SIZE};
+    use osmose_identifier::Identifier;
+    use crate::{single_verdict, ClientError, Decision, OsmoseClient, VerdictQuery};
+
+    /// Accepts the given number of connections one after another and serves
+    /// each of them with `serve`, which gets the number of the connection
+    fn fake_server<F>(connections: usize, serve: F) -> (SocketAddr, JoinHandle<()>)
+    where F: Fn(usize, &mut TcpStream) + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot bind test server");
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for i in 0..connections {
+                let (mut stream, _) = listener.accept().expect("Cannot accept connection");
+                stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                serve(i, &mut stream);
+            }
+        });
+        (address, server)
+    }
+
+    /// Reads a single request, returning its identifier
+    fn read_request<S: Read>(stream: &mut S) -> u64 {
+        let envelope: RequestEnvelope = read_message(stream, DEFAULT_MAX_FRAME_SIZE)
+            .expect("Cannot read request");
+        match envelope.body {
+            Some(RequestBody::single(request)) => request.get_request_id(),
+            other => panic!("Unexpected request: {:?}", other),
+        }
+    }
+
+    /// Allows the request, giving its identifier as the reason
+    fn reply<S: Write>(stream: &mut S, request_id: u64) {
+        let mut response = Response::new();
+        response.set_request_id(request_id);
+        response.set_decision(Decision::ALLOW);
+        response.set_reason(format!("request {}", request_id));
+        let mut envelope = ResponseEnvelope::new();
+        envelope.set_single(response);
+        write_message(stream, &envelope, DEFAULT_MAX_FRAME_SIZE).expect("Cannot send reply");
+    }
+
+    fn ask(client: &OsmoseClient) -> String {
+        let verdict = client.ask_for_verdict(&Identifier::from_given("process1", 1), b"test")
+            .expect("Verdict should be received");
+        assert!(verdict.is_allowed());
+        verdict.get_reason().to_owned()
+    }
+
+    #[test]
+    fn test_session() {
+        let (address, server) = fake_server(1, |_, stream| {
+            for _ in 0..3 {
+                let request_id = read_request(stream);
+                reply(stream, request_id);
+            }
+        });
+        let client = OsmoseClient::from_socket_address(address);
+        assert_eq!(ask(&client), "request 1");
+        assert_eq!(ask(&client), "request 2");
+        assert_eq!(ask(&client), "request 3");
+        server.join().expect("All requests should come over one connection");
+    }
+
+    #[test]
+    fn test_pipelined() {
+        // Nothing is answered until all the requests arrive
+        let (address, server) = fake_server(1, |_, stream| {
+            let request_ids: Vec<_> = (0..3).map(|_| read_request(stream)).collect();
+            for request_id in request_ids {
+                reply(stream, request_id);
+            }
+        });
+        let client = OsmoseClient::from_socket_address(address);
+        let source = Identifier::from_given("process1", 1);
+        let destination = Identifier::from_given("process2", 2);
+        let queries: Vec<_> = (0..3)
+            .map(|_| VerdictQuery { source: &source, destination: &destination, payload: b"" })
+            .collect();
+        let reasons: Vec<_> = client.ask_for_verdicts_pipelined(&queries).unwrap().iter()
+            .map(|verdict| verdict.get_reason().to_owned())
+            .collect();
+        assert_eq!(reasons, ["request 1", "request 2", "request 3"]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        // The first connection is closed after a single reply, as the
+        // server does with idle connections
+        let (address, server) = fake_server(2, |_, stream| {
+            let request_id = read_request(stream);
+            reply(stream, request_id);
+        });
+        let client = OsmoseClient::from_socket_address(address);
+        assert_eq!(ask(&client), "request 1");
+        assert_eq!(ask(&client), "request 2");
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        let path = std::env::temp_dir()
+            .join(format!("osmose_client_test_{}.sock", std::process::id()));
+        let listener = std::os::unix::net::UnixListener::bind(&path)
+            .expect("Cannot bind test server");
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Cannot accept connection");
+            for _ in 0..2 {
+                let request_id = read_request(&mut stream);
+                reply(&mut stream, request_id);
+            }
+        });
+        let client = OsmoseClient::from_unix_socket(&path);
+        assert_eq!(ask(&client), "request 1");
+        assert_eq!(ask(&client), "request 2");
+        server.join().unwrap();
+        std::fs::remove_file(&path).expect("Cannot remove test socket");
+    }
+
+    #[test]
+    fn test_single_verdict() {
+        let single = |request_id: u64, decision: Decision| {
+            let mut response = Response::new();
+            response.set_request_id(request_id);
+            response.set_decision(decision);
+            let mut envelope = ResponseEnvelope::new();
+            envelope.set_single(response);
+            envelope
+        };
+        let verdict = single_verdict(single(7, Decision::SOURCE_UNKNOWN), 7).unwrap();
+        assert_eq!(verdict.get_decision(), Decision::SOURCE_UNKNOWN);
+
+        match single_verdict(single(0, Decision::MALFORMED_MESSAGE), 7) {
+            Err(ClientError::Rejected(verdict)) => {
+                assert_eq!(verdict.get_decision(), Decision::MALFORMED_MESSAGE)
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match single_verdict(single(6, Decision::ALLOW), 7) {
+            Err(ClientError::UnexpectedResponse(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match single_verdict(ResponseEnvelope::new(), 7) {
+            Err(ClientError::UnexpectedResponse(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
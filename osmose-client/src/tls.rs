@@ -0,0 +1,126 @@
+use std::convert::TryFrom;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::pki_types::pem::PemObject;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::transport::Stream;
+
+
+/// TLS settings for talking to Osmose server over TCP
+///
+/// Connections over Unix domain sockets are not affected by these settings.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+}
+
+
+impl TlsConfig {
+    /// Returns TLS settings which trust server certificates signed by the
+    /// given authority
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - Name the server certificate has to be issued for
+    /// * `ca_file` - PEM file with the certificate authorities to trust
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use osmose_client::{OsmoseClient, TlsConfig};
+    ///
+    /// let mut client = OsmoseClient::new();
+    /// client.set_tls(TlsConfig::new("osmose.local", "/etc/osmose/ca.pem").unwrap());
+    /// ```
+    pub fn new<P: AsRef<Path>>(server_name: &str, ca_file: P) -> std::io::Result<Self> {
+        let config = config_builder(ca_file.as_ref())?.with_no_client_auth();
+        Self::from_config(server_name, config)
+    }
+
+    /// Returns TLS settings which also present a client certificate, as
+    /// required by servers with mutual TLS
+    ///
+    /// The server takes the common name of the certificate as the name of
+    /// this entity, so it has to match the name of its self ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - Name the server certificate has to be issued for
+    /// * `ca_file` - PEM file with the certificate authorities to trust
+    /// * `cert_file` - PEM file with the client certificate chain
+    /// * `key_file` - PEM file with the private key of the client
+    pub fn with_client_certificate<P, Q, R>(
+        server_name: &str, ca_file: P, cert_file: Q, key_file: R
+    ) -> std::io::Result<Self>
+    where P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path> {
+        let (cert_file, key_file) = (cert_file.as_ref(), key_file.as_ref());
+        let certificates = CertificateDer::pem_file_iter(cert_file)
+            .map_err(|e| invalid_data(cert_file, e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid_data(cert_file, e))?;
+        let key = PrivateKeyDer::from_pem_file(key_file)
+            .map_err(|e| invalid_data(key_file, e))?;
+        let config = config_builder(ca_file.as_ref())?
+            .with_client_auth_cert(certificates, key)
+            .map_err(|e| invalid_data(cert_file, e))?;
+        Self::from_config(server_name, config)
+    }
+
+    fn from_config(server_name: &str, config: ClientConfig) -> std::io::Result<Self> {
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        Ok(TlsConfig { server_name, config: Arc::new(config) })
+    }
+
+    /// Performs the TLS handshake over a connected socket
+    pub(crate) fn connect(&self, mut socket: TcpStream) -> std::io::Result<Stream> {
+        let mut connection = ClientConnection::new(
+            self.config.clone(), self.server_name.clone())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, socket))))
+    }
+
+    /// Performs the TLS handshake over a connected asynchronous socket
+    #[cfg(feature = "async")]
+    pub(crate) async fn connect_async(
+        &self, socket: tokio::net::TcpStream
+    ) -> std::io::Result<crate::transport::AsyncStream> {
+        let connector = tokio_rustls::TlsConnector::from(self.config.clone());
+        let stream = connector.connect(self.server_name.clone(), socket).await?;
+        Ok(crate::transport::AsyncStream::Tls(Box::new(stream)))
+    }
+}
+
+
+fn config_builder(
+    ca_file: &Path
+) -> std::io::Result<rustls::ConfigBuilder<ClientConfig, rustls::client::WantsClientCert>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(ca_file).map_err(|e| invalid_data(ca_file, e))? {
+        let certificate = certificate.map_err(|e| invalid_data(ca_file, e))?;
+        roots.add(certificate).map_err(|e| invalid_data(ca_file, e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid_data(ca_file, "no certificates found"));
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok(builder.with_root_certificates(roots))
+}
+
+
+fn invalid_data<E: std::fmt::Display>(path: &Path, error: E) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), error))
+}
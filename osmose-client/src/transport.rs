@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(feature = "tls")]
+pub(crate) use crate::tls::TlsConfig;
+
+
+/// Stand-in for TLS settings, which can never be given without the `tls`
+/// feature
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub(crate) enum TlsConfig {}
+
+
+#[cfg(not(feature = "tls"))]
+impl TlsConfig {
+    fn connect(&self, _socket: TcpStream) -> std::io::Result<Stream> {
+        match *self {}
+    }
+
+    #[cfg(feature = "async")]
+    async fn connect_async(
+        &self, _socket: tokio::net::TcpStream
+    ) -> std::io::Result<AsyncStream> {
+        match *self {}
+    }
+}
+
+
+/// Location of Osmose server
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerAddress::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            ServerAddress::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+
+impl ServerAddress {
+    /// Connects to the server, wrapping TCP connections in TLS if it is
+    /// configured
+    pub(crate) fn connect(&self, tls: Option<&TlsConfig>) -> std::io::Result<Stream> {
+        match self {
+            ServerAddress::Tcp(address) => {
+                let socket = TcpStream::connect(address)?;
+                match tls {
+                    Some(tls) => tls.connect(socket),
+                    None => Ok(Stream::Tcp(socket)),
+                }
+            },
+            #[cfg(unix)]
+            ServerAddress::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+}
+
+
+/// Blocking connection to Osmose server over any supported transport
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+
+#[cfg(feature = "tls")]
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Stream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            let _ = stream.conn.complete_io(&mut stream.sock);
+        }
+    }
+}
+
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+
+#[cfg(feature = "async")]
+pub(crate) use self::async_stream::AsyncStream;
+
+
+#[cfg(feature = "async")]
+mod async_stream {
+    use std::io::IoSlice;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::TcpStream;
+    #[cfg(unix)]
+    use tokio::net::UnixStream;
+
+    use super::{ServerAddress, TlsConfig};
+
+
+    impl ServerAddress {
+        pub(crate) async fn connect_async(
+            &self, tls: Option<&TlsConfig>
+        ) -> std::io::Result<AsyncStream> {
+            match self {
+                ServerAddress::Tcp(address) => {
+                    let socket = TcpStream::connect(address).await?;
+                    match tls {
+                        Some(tls) => tls.connect_async(socket).await,
+                        None => Ok(AsyncStream::Tcp(socket)),
+                    }
+                },
+                #[cfg(unix)]
+                ServerAddress::Unix(path) => {
+                    UnixStream::connect(path).await.map(AsyncStream::Unix)
+                },
+            }
+        }
+    }
+
+
+    /// Asynchronous connection to Osmose server over any supported transport
+    pub(crate) enum AsyncStream {
+        Tcp(TcpStream),
+        #[cfg(feature = "tls")]
+        Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+        #[cfg(unix)]
+        Unix(UnixStream),
+    }
+
+
+    impl AsyncRead for AsyncStream {
+        fn poll_read(
+            self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>
+        ) -> Poll<std::io::Result<()>> {
+            match self.get_mut() {
+                AsyncStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            }
+        }
+    }
+
+
+    impl AsyncWrite for AsyncStream {
+        fn poll_write(
+            self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]
+        ) -> Poll<std::io::Result<usize>> {
+            match self.get_mut() {
+                AsyncStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            }
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]
+        ) -> Poll<std::io::Result<usize>> {
+            match self.get_mut() {
+                AsyncStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write_vectored(cx, bufs),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            }
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            match self {
+                AsyncStream::Tcp(stream) => stream.is_write_vectored(),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => stream.is_write_vectored(),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => stream.is_write_vectored(),
+            }
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>, cx: &mut Context<'_>
+        ) -> Poll<std::io::Result<()>> {
+            match self.get_mut() {
+                AsyncStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            }
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>, cx: &mut Context<'_>
+        ) -> Poll<std::io::Result<()>> {
+            match self.get_mut() {
+                AsyncStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+                #[cfg(feature = "tls")]
+                AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+                #[cfg(unix)]
+                AsyncStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            }
+        }
+    }
+}
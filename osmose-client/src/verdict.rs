@@ -0,0 +1,87 @@
+use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+use osmose_generated::generated_proto::osmose::Decision as Decision;
+
+
+/// Decision made by Osmose server for a single request, with its explanation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    decision: Decision,
+    reason: String,
+    matched_rule: Option<String>,
+    trace: Vec<String>,
+}
+
+
+impl Verdict {
+    /// Returns `true` if the message is allowed to pass
+    pub fn is_allowed(&self) -> bool {
+        self.decision == Decision::ALLOW
+    }
+
+    pub fn get_decision(&self) -> Decision {
+        self.decision
+    }
+
+    /// Returns a human-readable explanation of the decision
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Returns the identifier of the rule which allowed the message, if any
+    pub fn get_matched_rule(&self) -> Option<&str> {
+        self.matched_rule.as_deref()
+    }
+
+    /// Returns the evaluation trace, which is only filled in by the server
+    /// if the client asked for explanation
+    pub fn get_trace(&self) -> &[String] {
+        &self.trace
+    }
+}
+
+
+impl From<&Response> for Verdict {
+    fn from(response: &Response) -> Self {
+        let matched_rule = match response.get_matched_rule() {
+            "" => None,
+            rule => Some(rule.to_owned()),
+        };
+        Verdict {
+            decision: response.get_decision(),
+            reason: response.get_reason().to_owned(),
+            matched_rule,
+            trace: response.get_trace().to_vec(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+    use osmose_generated::generated_proto::osmose::Decision as Decision;
+    use crate::Verdict;
+
+    #[test]
+    fn test_from_response() {
+        let mut response = Response::new();
+        response.set_decision(Decision::ALLOW);
+        response.set_reason("'a' may call 'b'".to_owned());
+        response.set_matched_rule("rule 1".to_owned());
+        response.set_trace(vec!["step 1".to_owned(), "step 2".to_owned()].into());
+        let verdict = Verdict::from(&response);
+        assert!(verdict.is_allowed());
+        assert_eq!(verdict.get_decision(), Decision::ALLOW);
+        assert_eq!(verdict.get_reason(), "'a' may call 'b'");
+        assert_eq!(verdict.get_matched_rule(), Some("rule 1"));
+        assert_eq!(verdict.get_trace(), ["step 1", "step 2"]);
+
+        let mut response = Response::new();
+        response.set_decision(Decision::DISALLOWED_DESTINATION);
+        let verdict = Verdict::from(&response);
+        assert!(!verdict.is_allowed());
+        assert_eq!(verdict.get_matched_rule(), None);
+        assert!(verdict.get_trace().is_empty());
+    }
+}
+
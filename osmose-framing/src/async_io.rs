@@ -0,0 +1,83 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use protobuf::Message;
+
+use crate::{encode_frame, FrameError, VarintDecoder};
+
+
+/// Asynchronous counterpart of `write_frame`
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W, body: &[u8], max_size: usize
+) -> Result<(), FrameError> {
+    if body.len() > max_size {
+        return Err(FrameError::TooLarge { size: body.len() as u64, max: max_size });
+    }
+
+    writer.write_all(&encode_frame(body)).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+
+/// Asynchronous counterpart of `read_frame`
+pub async fn read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R, max_size: usize
+) -> Result<Vec<u8>, FrameError> {
+    let mut decoder = VarintDecoder::default();
+    let mut byte = [0u8; 1];
+    if reader.read(&mut byte).await? == 0 {
+        return Err(FrameError::Closed);
+    }
+    let size = loop {
+        if let Some(value) = decoder.push(byte[0])? {
+            break value;
+        }
+        reader.read_exact(&mut byte).await?;
+    };
+    if size > max_size as u64 {
+        return Err(FrameError::TooLarge { size, max: max_size });
+    }
+
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+
+/// Asynchronous counterpart of `write_message`
+pub async fn write_message_async<M: Message, W: AsyncWrite + Unpin>(
+    writer: &mut W, message: &M, max_size: usize
+) -> Result<(), FrameError> {
+    let body = message.write_to_bytes()?;
+    write_frame_async(writer, &body, max_size).await
+}
+
+
+/// Asynchronous counterpart of `read_message`
+pub async fn read_message_async<M: Message, R: AsyncRead + Unpin>(
+    reader: &mut R, max_size: usize
+) -> Result<M, FrameError> {
+    let body = read_frame_async(reader, max_size).await?;
+    Ok(M::parse_from_bytes(&body)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{FrameError, read_frame_async, write_frame, write_frame_async};
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let mut buffer = Vec::new();
+        write_frame_async(&mut buffer, b"first", 1024).await.unwrap();
+        write_frame(&mut buffer, &[1u8; 300], 1024).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame_async(&mut reader, 1024).await.unwrap(), b"first");
+        assert_eq!(read_frame_async(&mut reader, 1024).await.unwrap(), vec![1u8; 300]);
+        match read_frame_async(&mut reader, 1024).await {
+            Err(FrameError::Closed) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use protobuf::Message;
+
+#[cfg(feature = "tokio")]
+mod async_io;
+
+#[cfg(feature = "tokio")]
+pub use crate::async_io::{read_frame_async, read_message_async, write_frame_async, write_message_async};
+
+
+/// Default upper bound for a single frame body, in bytes
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Maximum number of bytes a length prefix may occupy (varint-encoded u64)
+const MAX_VARINT_LENGTH: usize = 10;
+
+
+/// Errors which may occur while reading or writing a frame
+#[derive(Debug)]
+pub enum FrameError {
+    /// Underlying stream failed
+    Io(std::io::Error),
+    /// Peer closed the stream cleanly between two frames
+    Closed,
+    /// Frame length prefix is not a valid varint
+    InvalidLength,
+    /// Frame body is larger than the configured limit
+    TooLarge { size: u64, max: usize },
+    /// Frame body is not a valid Protobuf message
+    Protobuf(protobuf::ProtobufError),
+}
+
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "stream error: {}", e),
+            FrameError::Closed => write!(f, "connection closed by peer"),
+            FrameError::InvalidLength => write!(f, "invalid frame length prefix"),
+            FrameError::TooLarge { size, max } => write!(
+                f, "frame of {} bytes exceeds maximum frame size of {} bytes",
+                size, max),
+            FrameError::Protobuf(e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            FrameError::Protobuf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<std::io::Error> for FrameError {
+    fn from(error: std::io::Error) -> Self {
+        FrameError::Io(error)
+    }
+}
+
+
+impl From<protobuf::ProtobufError> for FrameError {
+    fn from(error: protobuf::ProtobufError) -> Self {
+        FrameError::Protobuf(error)
+    }
+}
+
+
+/// Writes a single frame: varint-encoded body length followed by the body
+///
+/// # Arguments
+///
+/// * `writer` - Stream to write the frame to
+/// * `body` - Raw frame contents
+/// * `max_size` - Maximum allowed body length in bytes
+pub fn write_frame<W: Write>(
+    writer: &mut W, body: &[u8], max_size: usize
+) -> Result<(), FrameError> {
+    if body.len() > max_size {
+        return Err(FrameError::TooLarge { size: body.len() as u64, max: max_size });
+    }
+
+    writer.write_all(&encode_frame(body))?;
+    writer.flush()?;
+    Ok(())
+}
+
+
+/// Reads a single frame written by `write_frame` and returns its body
+///
+/// Returns `FrameError::Closed` if the stream ends before the first byte of
+/// the frame, which is how a peer finishes a persistent connection.
+///
+/// The body is never read if its declared length exceeds `max_size`, so
+/// an oversized frame leaves the stream in an undefined position and the
+/// connection should be dropped.
+///
+/// # Arguments
+///
+/// * `reader` - Stream to read the frame from
+/// * `max_size` - Maximum allowed body length in bytes
+pub fn read_frame<R: Read>(
+    reader: &mut R, max_size: usize
+) -> Result<Vec<u8>, FrameError> {
+    let size = decode_varint(reader)?;
+    if size > max_size as u64 {
+        return Err(FrameError::TooLarge { size, max: max_size });
+    }
+
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+
+/// Serializes a Protobuf message and writes it as a single frame
+pub fn write_message<M: Message, W: Write>(
+    writer: &mut W, message: &M, max_size: usize
+) -> Result<(), FrameError> {
+    let body = message.write_to_bytes()?;
+    write_frame(writer, &body, max_size)
+}
+
+
+/// Reads a single frame and parses it as a Protobuf message
+pub fn read_message<M: Message, R: Read>(
+    reader: &mut R, max_size: usize
+) -> Result<M, FrameError> {
+    let body = read_frame(reader, max_size)?;
+    Ok(M::parse_from_bytes(&body)?)
+}
+
+
+/// Tells whether the bytes begin with a whole frame, so that reading it from
+/// a buffer holding them would not wait for the stream
+///
+/// An invalid length prefix counts as a whole frame, as reading it fails
+/// right away.
+pub fn starts_with_frame(bytes: &[u8]) -> bool {
+    let mut decoder = VarintDecoder::default();
+    for (i, byte) in bytes.iter().enumerate() {
+        match decoder.push(*byte) {
+            Ok(Some(size)) => return (bytes.len() - i - 1) as u64 >= size,
+            Ok(None) => (),
+            Err(_) => return true,
+        }
+    }
+    false
+}
+
+
+fn encode_frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(MAX_VARINT_LENGTH + body.len());
+    encode_varint(body.len() as u64, &mut frame);
+    frame.extend_from_slice(body);
+    frame
+}
+
+
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+
+fn decode_varint<R: Read>(reader: &mut R) -> Result<u64, FrameError> {
+    let mut decoder = VarintDecoder::default();
+    let mut byte = [0u8; 1];
+    if read_first_byte(reader, &mut byte)? == 0 {
+        return Err(FrameError::Closed);
+    }
+    loop {
+        if let Some(value) = decoder.push(byte[0])? {
+            return Ok(value);
+        }
+        reader.read_exact(&mut byte)?;
+    }
+}
+
+
+/// Incremental varint decoder shared by blocking and asynchronous readers
+#[derive(Default)]
+struct VarintDecoder {
+    value: u64,
+    length: usize,
+}
+
+
+impl VarintDecoder {
+    /// Consumes the next byte, returning the value once it is complete
+    fn push(&mut self, byte: u8) -> Result<Option<u64>, FrameError> {
+        if self.length == MAX_VARINT_LENGTH {
+            return Err(FrameError::InvalidLength);
+        }
+        self.value |= u64::from(byte & 0x7f) << (7 * self.length);
+        self.length += 1;
+        if byte & 0x80 == 0 {
+            Ok(Some(self.value))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+
+fn read_first_byte<R: Read>(
+    reader: &mut R, byte: &mut [u8; 1]
+) -> Result<usize, FrameError> {
+    loop {
+        match reader.read(byte) {
+            Ok(len) => return Ok(len),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FrameError::Io(e)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{FrameError, read_frame, starts_with_frame, write_frame};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut buffer = Vec::new();
+        let large = vec![7u8; 100_000];
+        write_frame(&mut buffer, b"first", 1024 * 1024).unwrap();
+        write_frame(&mut buffer, &large, 1024 * 1024).unwrap();
+        write_frame(&mut buffer, b"", 1024 * 1024).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader, 1024 * 1024).unwrap(), b"first");
+        assert_eq!(read_frame(&mut reader, 1024 * 1024).unwrap(), large);
+        assert_eq!(read_frame(&mut reader, 1024 * 1024).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut buffer = Vec::new();
+        match write_frame(&mut buffer, &[0u8; 16], 8) {
+            Err(FrameError::TooLarge { size: 16, max: 8 }) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(buffer.is_empty());
+
+        write_frame(&mut buffer, &[0u8; 16], 16).unwrap();
+        match read_frame(&mut Cursor::new(buffer), 8) {
+            Err(FrameError::TooLarge { size: 16, max: 8 }) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"truncated", 1024).unwrap();
+        buffer.pop();
+        match read_frame(&mut Cursor::new(buffer), 1024) {
+            Err(FrameError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof)
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_closed() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"last", 1024).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader, 1024).unwrap(), b"last");
+        match read_frame(&mut reader, 1024) {
+            Err(FrameError::Closed) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let buffer = vec![0xffu8; 16];
+        match read_frame(&mut Cursor::new(buffer), 1024) {
+            Err(FrameError::InvalidLength) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+    #[test]
+    fn test_starts_with_frame() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &[7u8; 200], 1024).unwrap();
+
+        assert!(starts_with_frame(&buffer));
+        assert!(!starts_with_frame(&buffer[..buffer.len() - 1]));
+        assert!(!starts_with_frame(&buffer[..1]));
+        assert!(!starts_with_frame(&[]));
+        assert!(starts_with_frame(&[0]));
+        assert!(starts_with_frame(&[0xffu8; 16]));
+    }
+}
@@ -8,9 +8,9 @@ fn main() {
     let generated_dir = "./src/generated";
 
     if Path::new(&generated_dir).exists() {
-        fs::remove_dir_all(&generated_dir).unwrap();
+        fs::remove_dir_all(generated_dir).unwrap();
     }
-    fs::create_dir(&generated_dir).unwrap();
+    fs::create_dir(generated_dir).unwrap();
 
     protobuf_codegen_pure::Codegen::new()
         .customize(Customize {
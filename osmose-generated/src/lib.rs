@@ -1,3 +1,5 @@
+// Generated code predates these lints, and box_pointers it allows is removed
+#[allow(renamed_and_removed_lints, unused_parens, mismatched_lifetime_syntaxes)]
 pub mod generated_proto {
     include!("./generated/mod.rs");
 }
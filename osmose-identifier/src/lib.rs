@@ -1,20 +1,38 @@
+use std::collections::BTreeMap;
+
 use osmose_generated::generated_proto::osmose::Identifier as ProtoIdentifier;
 
 
-#[derive(Debug, Hash, Clone)]
+#[derive(Debug, Hash, Clone, PartialEq, Eq)]
 pub struct Identifier {
     name: String,
     id: u64,
+    labels: BTreeMap<String, String>,
 }
 
 
 impl Identifier {
     pub fn new() -> Self {
-        Identifier { name: "".to_owned(), id: u64::from(std::process::id()) }
+        Identifier {
+            name: "".to_owned(),
+            id: u64::from(std::process::id()),
+            labels: BTreeMap::new(),
+        }
     }
 
     pub fn from_given(name: &str, id: u64) -> Self {
-        Identifier { name: name.to_owned(), id: id }
+        Identifier { name: name.to_owned(), id, labels: BTreeMap::new() }
+    }
+
+    /// Returns the identifier with the label set, replacing any previous
+    /// value of the key
+    pub fn with_label(mut self, key: &str, value: &str) -> Self {
+        self.set_label(key, value);
+        self
+    }
+
+    pub fn set_label(&mut self, key: &str, value: &str) {
+        self.labels.insert(key.to_owned(), value.to_owned());
     }
 
     pub fn get_name(&self) -> &str {
@@ -24,6 +42,14 @@ impl Identifier {
     pub fn get_id(&self) -> u64 {
         self.id
     }
+
+    pub fn get_label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+
+    pub fn get_labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
 }
 
 
@@ -31,7 +57,11 @@ impl From<&ProtoIdentifier> for Identifier {
     fn from(proto_id: &ProtoIdentifier) -> Self {
         Identifier{
             name: proto_id.get_name().to_owned(),
-            id: proto_id.get_id()
+            id: proto_id.get_id(),
+            labels: proto_id.get_labels()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
         }
     }
 }
@@ -42,17 +72,18 @@ impl From<&Identifier> for ProtoIdentifier {
         let mut proto_id = ProtoIdentifier::new();
         proto_id.set_name(id.get_name().to_owned());
         proto_id.set_id(id.get_id());
+        proto_id.set_labels(id.get_labels()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect());
         proto_id
     }
 }
 
 
-impl PartialEq for Identifier {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.id == other.id
+impl Default for Identifier {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-
-impl Eq for Identifier {}
-
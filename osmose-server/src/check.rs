@@ -0,0 +1,186 @@
+use std::io::Write;
+
+use clap::{App, Arg, ArgMatches};
+
+use protobuf::well_known_types::Any;
+
+use osmose_generated::generated_proto::osmose::Decision as Decision;
+use osmose_generated::generated_proto::osmose::DecisionRequest as Request;
+use osmose_generated::generated_proto::osmose::Identifier as ProtoIdentifier;
+use osmose_identifier::Identifier;
+
+use crate::rules_database::RulesDatabase;
+use crate::server;
+
+
+/// Exit status when the checked call is not allowed
+pub const EXIT_NOT_ALLOWED: i32 = 1;
+
+/// Exit status when the call cannot be checked, as the payload file cannot
+/// be read or the verdict cannot be printed, `EX_IOERR` of sysexits
+pub const EXIT_CHECK_FAILED: i32 = 74;
+
+
+/// Describes the `check` subcommand, which evaluates a single call against
+/// the rules without starting the server
+pub fn command<'a>() -> App<'a> {
+    App::new("check")
+        .about("Evaluates a single call against the rules as the server would, printing \
+                the decision and its reason, and exits with 0 if the call is allowed, \
+                1 if it is not and 74 if it cannot be checked")
+        .args(identity_args(
+            ["from", "from-pid", "from-label"], "Sets the name of the source of the call"))
+        .args(identity_args(
+            ["to", "to-pid", "to-label"], "Sets the name of the destination of the call"))
+        .arg(Arg::new("payload-file")
+            .long("payload-file")
+            .value_name("path")
+            .help("Reads the payload of the call from the file [default: empty payload]")
+            .takes_value(true))
+        .arg(Arg::new("type-url")
+            .long("type-url")
+            .value_name("url")
+            .help("Sends the payload as google.protobuf.Any of the given type URL")
+            .takes_value(true))
+        .arg(Arg::new("explain")
+            .long("explain")
+            .help("Prints the evaluation steps as well"))
+}
+
+
+/// Arguments describing one side of the call by its name, process id and
+/// labels
+fn identity_args<'a>(names: [&'a str; 3], help: &'a str) -> [Arg<'a>; 3] {
+    let [name, pid, label] = names;
+    [
+        Arg::new(name)
+            .long(name)
+            .value_name("name")
+            .help(help)
+            .takes_value(true)
+            .required(true),
+        Arg::new(pid)
+            .long(pid)
+            .value_name("id")
+            .help("Sets the process id of the identity")
+            .default_value("0")
+            .validator(|id| id.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+            .takes_value(true),
+        Arg::new(label)
+            .long(label)
+            .value_name("key=value")
+            .help("Sets a label of the identity, may be given several times")
+            .validator(|label| match label.split_once('=') {
+                Some((key, _)) if !key.is_empty() => Ok(()),
+                _ => Err("label should be given as key=value"),
+            })
+            .multiple_occurrences(true)
+            .takes_value(true),
+    ]
+}
+
+
+/// Evaluates the call described by the arguments of the `check` subcommand
+/// and prints the verdict, returning the exit status
+pub fn run(
+    args: &ArgMatches, rules: &RulesDatabase, out: &mut dyn Write
+) -> std::io::Result<i32> {
+    let payload = match args.value_of("payload-file") {
+        Some(path) => std::fs::read(path).map_err(|e| std::io::Error::new(
+            e.kind(), format!("Cannot read payload file {}: {}", path, e)))?,
+        None => Vec::new(),
+    };
+    let mut request = Request::new();
+    request.set_source(ProtoIdentifier::from(&identifier(args, "from")));
+    request.set_destination(ProtoIdentifier::from(&identifier(args, "to")));
+    request.set_explain(args.is_present("explain"));
+    match args.value_of("type-url") {
+        Some(type_url) => {
+            let mut typed = Any::new();
+            typed.set_type_url(type_url.to_owned());
+            typed.set_value(payload);
+            request.set_typed_payload(typed);
+        },
+        None => request.set_payload(payload),
+    }
+
+    let response = server::process_offline(&request, rules);
+    writeln!(out, "Decision: {:?}", response.get_decision())?;
+    writeln!(out, "Reason: {}", response.get_reason())?;
+    if !response.get_matched_rule().is_empty() {
+        writeln!(out, "Rule: {}", response.get_matched_rule())?;
+    }
+    if !response.get_trace().is_empty() {
+        writeln!(out, "Trace:")?;
+        for step in response.get_trace() {
+            writeln!(out, "  {}", step)?;
+        }
+    }
+    Ok(match response.get_decision() {
+        Decision::ALLOW => 0,
+        _ => EXIT_NOT_ALLOWED,
+    })
+}
+
+
+fn identifier(args: &ArgMatches, side: &str) -> Identifier {
+    let id = args.value_of(format!("{}-pid", side)).unwrap().parse().unwrap();
+    let mut identifier = Identifier::from_given(args.value_of(side).unwrap(), id);
+    for label in args.values_of(format!("{}-label", side)).into_iter().flatten() {
+        let (key, value) = label.split_once('=').expect("Labels should be validated");
+        identifier.set_label(key, value);
+    }
+    identifier
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::check::{command, run};
+    use crate::rules_database::{LoadOptions, RulesDatabase};
+
+    #[test]
+    fn test_check() {
+        let config_name = "test_check_cfg.policy";
+        let config = "allow a labels \"env=prod\" -> b pid 7 when payload.size < 4\n";
+        std::fs::write(config_name, config).expect("Cannot create test configuration file");
+        let payload_name = "test_check_payload.bin";
+        std::fs::write(payload_name, b"1234").expect("Cannot create test payload file");
+        let result = std::panic::catch_unwind(|| {
+            let options = LoadOptions { match_pid: true, ..Default::default() };
+            let db = RulesDatabase::load(
+                std::path::Path::new(config_name), Default::default(), options)
+                .unwrap();
+            let check = |args: &[&str]| {
+                let args = command().get_matches_from(
+                    ["check", "--from", "a", "--from-label", "env=prod", "--to", "b"].iter()
+                        .chain(args));
+                let mut out = Vec::new();
+                let status = run(&args, &db, &mut out).unwrap();
+                (status, String::from_utf8(out).unwrap())
+            };
+
+            let (status, out) = check(&["--to-pid", "7"]);
+            assert_eq!(status, 0);
+            assert_eq!(
+                out, "Decision: ALLOW\nReason: 'a' may call 'b' by rule line 1\nRule: line 1\n");
+
+            let (status, out) = check(&["--to-pid", "8", "--explain"]);
+            assert_eq!(status, 1);
+            assert!(out.starts_with("Decision: DISALLOWED_DESTINATION\n"), "{}", out);
+            assert!(out.contains("Trace:\n  "), "{}", out);
+
+            let (status, out) = check(&["--to-pid", "7", "--payload-file", payload_name]);
+            assert_eq!(status, 1);
+            assert!(out.starts_with("Decision: DISALLOWED_MESSAGE\n"), "{}", out);
+
+            let args = command().get_matches_from(
+                ["check", "--from", "a", "--to", "b", "--payload-file", "test_check_missing.bin"]);
+            let error = run(&args, &db, &mut Vec::new()).unwrap_err();
+            assert!(error.to_string().starts_with("Cannot read payload file "), "{}", error);
+        });
+        std::fs::remove_file(config_name).expect("Cannot remove test configuration file");
+        std::fs::remove_file(payload_name).expect("Cannot remove test payload file");
+        assert!(result.is_ok())
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+
+/// Selector of identities by their labels, such as
+/// `env=prod, team in (payments, billing), !canary`
+///
+/// A selector is a comma-separated list of requirements, all of which have
+/// to hold:
+///
+/// * `key=value` or `key==value` - the label is set to the value
+/// * `key!=value` - the label is not set to the value, or not set at all
+/// * `key in (a, b)` - the label is set to one of the values
+/// * `key notin (a, b)` - the label is not set to any of the values
+/// * `key` - the label is set
+/// * `!key` - the label is not set
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelSelector {
+    text: String,
+    requirements: Vec<Requirement>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+
+impl Requirement {
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let label = |key: &String| labels.get(key);
+        match self {
+            Requirement::Equals(key, value) => label(key) == Some(value),
+            Requirement::NotEquals(key, value) => label(key) != Some(value),
+            Requirement::In(key, values) => label(key).is_some_and(|label| values.contains(label)),
+            Requirement::NotIn(key, values) => !label(key).is_some_and(|label| values.contains(label)),
+            Requirement::Exists(key) => label(key).is_some(),
+            Requirement::NotExists(key) => label(key).is_none(),
+        }
+    }
+}
+
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}'", self.text)
+    }
+}
+
+
+impl LabelSelector {
+    pub fn parse(text: &str) -> Result<LabelSelector, String> {
+        let mut parser = Parser { text, position: 0 };
+        let mut requirements = vec![parser.requirement()?];
+        while parser.eat(",") {
+            requirements.push(parser.requirement()?);
+        }
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("',' or end of selector"));
+        }
+        Ok(LabelSelector { text: text.trim().to_owned(), requirements })
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| requirement.matches(labels))
+    }
+}
+
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes the token if the rest of the selector starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "invalid label selector '{}': expected {} at position {}",
+            self.text, expected, self.position + 1)
+    }
+
+    /// Reads a label key or value
+    fn word(&mut self, what: &str) -> Result<String, String> {
+        self.skip_whitespace();
+        let length = self.rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+            .unwrap_or_else(|| self.rest().len());
+        if length == 0 {
+            return Err(self.error(what));
+        }
+        let word = self.rest()[..length].to_owned();
+        self.position += length;
+        Ok(word)
+    }
+
+    fn values(&mut self) -> Result<Vec<String>, String> {
+        if !self.eat("(") {
+            return Err(self.error("'('"));
+        }
+        let mut values = vec![self.word("value")?];
+        while self.eat(",") {
+            values.push(self.word("value")?);
+        }
+        if !self.eat(")") {
+            return Err(self.error("',' or ')'"));
+        }
+        Ok(values)
+    }
+
+    fn requirement(&mut self) -> Result<Requirement, String> {
+        if self.eat("!") {
+            return Ok(Requirement::NotExists(self.word("label key")?));
+        }
+        let key = self.word("label key")?;
+        if self.eat("!=") {
+            Ok(Requirement::NotEquals(key, self.word("value")?))
+        } else if self.eat("==") || self.eat("=") {
+            Ok(Requirement::Equals(key, self.word("value")?))
+        } else if self.eat("notin ") {
+            Ok(Requirement::NotIn(key, self.values()?))
+        } else if self.eat("in ") {
+            Ok(Requirement::In(key, self.values()?))
+        } else {
+            Ok(Requirement::Exists(key))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::labels::LabelSelector;
+
+    fn matches(selector: &str, labels: &[(&str, &str)]) -> bool {
+        let labels: BTreeMap<_, _> = labels.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        LabelSelector::parse(selector).unwrap().matches(&labels)
+    }
+
+    #[test]
+    fn test_matches() {
+        let prod = [("env", "prod"), ("team", "payments")];
+        assert!(matches("env=prod", &prod));
+        assert!(matches(" env == prod , team in (billing, payments)", &prod));
+        assert!(matches("env!=dev, team notin (search), !canary, team", &prod));
+        assert!(!matches("env=prod, canary", &prod));
+        assert!(!matches("team in (billing)", &prod));
+        assert!(matches("team notin (billing)", &[]));
+        assert!(matches("k8s.io/app=web-1", &[("k8s.io/app", "web-1")]));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for selector in ["", "env=", "env in prod", "env in (a b)", "env=prod,", "=prod", "env=prod)"] {
+            assert!(LabelSelector::parse(selector).is_err(), "{}", selector);
+        }
+        assert_eq!(
+            LabelSelector::parse("env in (a b)").unwrap_err(),
+            "invalid label selector 'env in (a b)': expected ',' or ')' at position 11");
+    }
+}
@@ -1,23 +1,43 @@
+mod check;
+mod labels;
+mod message_rules;
+mod name_index;
+mod policy;
+mod reload;
 mod rules_database;
+mod rules_files;
+mod rules_format;
+mod schema;
+mod server;
+mod tls;
+mod wire;
 
-use std::thread;
 use std::sync::Arc;
-use std::net::{TcpListener, TcpStream, Shutdown};
-use std::io::Read;
+use std::time::Duration;
 
-use crate::rules_database::RulesDatabase;
+use crate::reload::Reloader;
+use crate::rules_database::{LoadOptions, RulesDatabase};
+use crate::schema::SchemaRegistry;
+use crate::server::{Listener, ServerConfig};
 
-use protobuf::Message;
+use tokio::sync::{watch, Semaphore};
 
-use log;
 use env_logger::Env;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 
-use osmose_generated::generated_proto::osmose::DecisionRequest as Request;
-use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
-use osmose_generated::generated_proto::osmose::Decision as Decision;
+use osmose_framing::DEFAULT_MAX_FRAME_SIZE;
+
+/// Exit status when the rules file or the descriptor sets cannot be loaded,
+/// `EX_CONFIG` of sysexits
+const EXIT_INVALID_RULES: i32 = 78;
+
+/// Exit status when the Unix socket is already in use, `EX_CANTCREAT` of
+/// sysexits
+#[cfg(unix)]
+const EXIT_SOCKET_IN_USE: i32 = 73;
 
 fn main() {
+    let default_max_frame_size = DEFAULT_MAX_FRAME_SIZE.to_string();
     let args = App::new("Osmose server executable")
         .version("0.1")
         .author("Mark K. <atanzuuu@gmail.com>")
@@ -27,83 +47,309 @@ fn main() {
             .long("port")
             .default_value("9061")
             .takes_value(true))
-        .arg(Arg::new("rules")
-            .short('r')
-            .long("rules")
-            .value_name("rules")
-            .about("Sets a rules config file")
-            .takes_value(true)
-            .required(true))
+        .arg(Arg::new("tls-cert")
+            .long("tls-cert")
+            .value_name("path")
+            .help("Serves TCP connections over TLS with the PEM certificate chain")
+            .requires("tls-key")
+            .takes_value(true))
+        .arg(Arg::new("tls-key")
+            .long("tls-key")
+            .value_name("path")
+            .help("Sets the PEM private key for --tls-cert")
+            .requires("tls-cert")
+            .takes_value(true))
+        .arg(Arg::new("tls-client-ca")
+            .long("tls-client-ca")
+            .value_name("path")
+            .help("Requires TLS clients to present a certificate signed by this PEM \
+                   authority, whose common name has to match the claimed destination")
+            .requires("tls-cert")
+            .takes_value(true))
+        .args(rules_args())
+        .arg(Arg::new("watch-rules")
+            .long("watch-rules")
+            .help("Reloads the rules when the rules file changes, in addition to \
+                   reloading them on SIGHUP"))
+        .arg(Arg::new("max-frame-size")
+            .long("max-frame-size")
+            .value_name("bytes")
+            .help("Sets the maximum size of a single request frame")
+            .default_value(&default_max_frame_size)
+            .validator(positive_count)
+            .takes_value(true))
+        .arg(Arg::new("max-connections")
+            .long("max-connections")
+            .value_name("count")
+            .help("Sets the maximum number of connections served at once")
+            .default_value("1024")
+            .validator(positive_count)
+            .takes_value(true))
+        .arg(Arg::new("idle-timeout")
+            .long("idle-timeout")
+            .value_name("seconds")
+            .help("Closes connections without requests for this long, 0 to disable")
+            .default_value("60")
+            .validator(|seconds| seconds.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+            .takes_value(true))
+        .arg(Arg::new("unix-socket")
+            .long("unix-socket")
+            .value_name("path")
+            .help("Also listens on a Unix domain socket at the given path")
+            .takes_value(true))
+        .arg(Arg::new("no-tcp")
+            .long("no-tcp")
+            .help("Does not listen on TCP, requires --unix-socket")
+            .requires("unix-socket"))
+        .arg(Arg::new("no-peer-verification")
+            .long("no-peer-verification")
+            .help("Trusts identities claimed over the Unix socket without \
+                   checking them against the credentials of the peer process"))
+        .arg(Arg::new("worker-threads")
+            .long("worker-threads")
+            .value_name("count")
+            .help("Sets the number of runtime worker threads [default: number of CPUs]")
+            .validator(positive_count)
+            .takes_value(true))
+        .subcommand(check::command().args(rules_args()))
+        .subcommand_negates_reqs(true)
         .get_matches();
 
     env_logger::Builder::from_env(
         Env::default().default_filter_or("warn")).init();
 
+    if let Some(("check", args)) = args.subcommand() {
+        let (_, rules) = load_rules(args);
+        let status = check::run(args, &rules, &mut std::io::stdout()).unwrap_or_else(|e| {
+            eprintln!("Cannot check the call: {}", e);
+            check::EXIT_CHECK_FAILED
+        });
+        std::process::exit(status);
+    }
+
     let rules_path = std::path::Path::new(
         args.value_of("rules").expect("No rules file path given")
     );
-    let rules_database = Arc::new(RulesDatabase::new(&rules_path));
+    // Connection tasks share the database through cheap reference counting,
+    // and the reloader swaps in a new one for the requests which follow
+    let (options, rules) = load_rules(&args);
+    let (rules_sender, rules_database) = watch::channel(Arc::new(rules));
+    let descriptor_sets = args.values_of("descriptor-set").into_iter().flatten()
+        .map(std::path::PathBuf::from)
+        .collect();
+    let reloader = Reloader::new(rules_path, descriptor_sets, options, rules_sender);
+
+    let idle_timeout = args.value_of("idle-timeout").unwrap()
+        .parse::<u64>()
+        .expect("Idle timeout should be a number of seconds");
+    let config = ServerConfig {
+        max_frame_size: args.value_of("max-frame-size").unwrap()
+            .parse::<usize>()
+            .expect("Maximum frame size should be a number of bytes"),
+        max_connections: args.value_of("max-connections").unwrap()
+            .parse::<usize>()
+            .expect("Maximum number of connections should be a number"),
+        idle_timeout: match idle_timeout {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        },
+        verify_peer: !args.is_present("no-peer-verification"),
+    };
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = args.value_of("worker-threads") {
+        runtime.worker_threads(
+            threads.parse::<usize>().expect("Worker threads should be a number"));
+    }
+    let runtime = runtime.build().expect("Cannot start async runtime");
 
     let port = args.value_of("port").unwrap().parse::<u16>().unwrap();
     let local_address = format!("0.0.0.0:{}", port);
-    let listener = TcpListener::bind(&local_address)
-        .expect("Cannot open server socket");
-
-    // accept connections and process them, spawning a new thread for each one
-    log::info!("Server listening on {}", local_address);
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                log::debug!("New OSMOSE connection: {}", stream.peer_addr().unwrap());
-                //TODO Use scope from rayon to get rid of this cloning
-                let db_arc = rules_database.clone();
-                thread::spawn(move || {
-                    handle_client(stream, db_arc);
-                });
+    let tls_acceptor = args.value_of("tls-cert").map(|cert_path| {
+        tls::make_acceptor(
+            std::path::Path::new(cert_path),
+            std::path::Path::new(args.value_of("tls-key").expect("No TLS key path given")),
+            args.value_of("tls-client-ca").map(std::path::Path::new),
+        ).unwrap_or_else(|e| panic!("Cannot configure TLS: {}", e))
+    });
+    runtime.block_on(async {
+        let mut listeners = Vec::new();
+        if !args.is_present("no-tcp") {
+            let listener = tokio::net::TcpListener::bind(&local_address).await
+                .expect("Cannot open server socket");
+            match tls_acceptor {
+                Some(acceptor) => {
+                    log::info!("Server listening on {} with TLS", local_address);
+                    listeners.push(Listener::Tls {
+                        listener,
+                        acceptor,
+                        client_auth: args.is_present("tls-client-ca"),
+                    });
+                },
+                None => {
+                    log::info!("Server listening on {}", local_address);
+                    listeners.push(Listener::Tcp(listener));
+                },
             }
-            Err(error) => {
-                log::warn!("Stream error: {}", error);
+        }
+        if let Some(path) = args.value_of("unix-socket") {
+            listeners.push(bind_unix_socket(std::path::Path::new(path)));
+        }
+
+        tokio::spawn(reloader.run(args.is_present("watch-rules")));
+
+        let limit = Arc::new(Semaphore::new(config.max_connections));
+        let servers: Vec<_> = listeners.into_iter()
+            .map(|listener| tokio::spawn(server::serve(
+                listener, rules_database.clone(), limit.clone(), config)))
+            .collect();
+        let servers = async {
+            for server in servers {
+                server.await.expect("Server task panicked");
             }
+        };
+        tokio::select! {
+            _ = servers => (),
+            _ = shutdown_signal() => log::info!("Received shutdown signal"),
+        }
+    });
+    if let Some(path) = args.value_of("unix-socket") {
+        if let Err(e) = std::fs::remove_file(path) {
+            log::warn!("Cannot remove Unix socket {}: {}", path, e);
         }
     }
     log::info!("Server terminating");
-    drop(listener);
 }
 
 
-fn handle_client(mut stream: TcpStream, db: Arc<RulesDatabase>) {
-    let mut data = [0 as u8; 4096];
-    let mut response = Response::new();
-    match stream.read(&mut data) {
-        Ok(len) => {
-            match Request::parse_from_bytes(&data[0..len]) {
-                Ok(request) => {
-                    log::debug!("Processing request {:?}", request);
-                    let decision = db.is_call_allowed(
-                        &osmose_identifier::Identifier::from(request.get_source()),
-                        &osmose_identifier::Identifier::from(request.get_destination())
-                    );
+/// Waits for SIGINT or SIGTERM
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Cannot handle SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+
+/// Waits for Ctrl-C
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c().await.expect("Cannot handle Ctrl-C");
+}
+
+
+/// Arguments selecting the rules and how to load them, shared by the server
+/// and the `check` subcommand
+fn rules_args<'a>() -> [Arg<'a>; 5] {
+    [
+        Arg::new("rules")
+            .short('r')
+            .long("rules")
+            .value_name("rules")
+            .help("Sets a rules config file, or a directory whose rules files are all loaded")
+            .takes_value(true)
+            .required(true),
+        Arg::new("rules-format")
+            .long("rules-format")
+            .value_name("format")
+            .help("Sets the format of the rules file [default: guessed by its extension]")
+            .possible_values(["json", "yaml", "toml", "policy"])
+            .takes_value(true),
+        Arg::new("duplicate-sources")
+            .long("duplicate-sources")
+            .value_name("policy")
+            .help("Sets how entries repeating an earlier source are loaded: rejected, \
+                   merged into the earlier entry or replacing it with a warning \
+                   [default: last-wins within a file, merge across files]")
+            .possible_values(["error", "merge", "last-wins"])
+            .takes_value(true),
+        Arg::new("descriptor-set")
+            .long("descriptor-set")
+            .value_name("path")
+            .help("Loads message types used by message rules from a binary \
+                   FileDescriptorSet, may be given several times")
+            .multiple_occurrences(true)
+            .takes_value(true),
+        Arg::new("match-pid")
+            .long("match-pid")
+            .help("Applies rules only to the ids given by their pid, which is not checked \
+                   otherwise"),
+    ]
+}
+
+
+/// Accepts counts and sizes of one and more
+fn positive_count(count: &str) -> Result<(), String> {
+    match count.parse::<usize>() {
+        Ok(0) => Err("should be at least 1".to_owned()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+
+/// Loads the rules given by `rules_args`, exiting if they are invalid
+fn load_rules(args: &ArgMatches) -> (LoadOptions, RulesDatabase) {
+    let rules_path = std::path::Path::new(
+        args.value_of("rules").expect("No rules file path given")
+    );
+    let schemas = SchemaRegistry::load(
+        args.values_of("descriptor-set").into_iter().flatten().map(std::path::Path::new)
+    ).unwrap_or_else(|e| {
+        eprintln!("Cannot load descriptor set: {}", e);
+        std::process::exit(EXIT_INVALID_RULES);
+    });
+    let options = LoadOptions {
+        duplicates: args.value_of("duplicate-sources").map(|policy| policy.parse().unwrap()),
+        format: args.value_of("rules-format").map(|format| format.parse().unwrap()),
+        match_pid: args.is_present("match-pid"),
+    };
+    let rules = RulesDatabase::load(rules_path, Arc::new(schemas), options).unwrap_or_else(|e| {
+        eprintln!("Invalid rules: {}", e);
+        std::process::exit(EXIT_INVALID_RULES);
+    });
+    log::info!("Loaded rules from {:?}: {}", rules_path, rules.get_report());
+    (options, rules)
+}
+
 
-                    log::debug!("Verdict for request is {:?}", decision);
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> Listener {
+    use std::os::unix::fs::FileTypeExt;
 
-                    response.set_decision(decision);
-                    response.write_to_writer(&mut stream).unwrap();
+    // A socket file left behind by a previous run would make binding fail,
+    // while one another server still listens on has to be left to it
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path).expect("Cannot remove stale Unix socket");
+                },
+                Ok(_) => {
+                    eprintln!("Unix socket {} is already in use", path.display());
+                    std::process::exit(EXIT_SOCKET_IN_USE);
+                },
+                Err(e) => {
+                    eprintln!("Cannot check whether Unix socket {} is in use: {}",
+                              path.display(), e);
+                    std::process::exit(EXIT_SOCKET_IN_USE);
                 },
-                Err(parse_error) => {
-                    log::error!(
-                        "Parse error: {}. Terminating connection with {}",
-                        parse_error, stream.peer_addr().unwrap());
-                    response.set_decision(Decision::MALFORMED_MESSAGE);
-                    response.write_to_writer(&mut stream).unwrap();
-                }
             }
-        },
-        Err(stream_error) => {
-            log::error!(
-                "Stream error occurred: {}, terminating connection with {}",
-                stream_error, stream.peer_addr().unwrap());
         }
     }
-    stream.shutdown(Shutdown::Both).unwrap();
+    let listener = tokio::net::UnixListener::bind(path)
+        .expect("Cannot open Unix server socket");
+    log::info!("Server listening on {}", path.display());
+    Listener::Unix(listener)
 }
 
+
+#[cfg(not(unix))]
+fn bind_unix_socket(_path: &std::path::Path) -> Listener {
+    panic!("Unix domain sockets are not supported on this platform");
+}
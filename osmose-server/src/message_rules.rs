@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use tinyjson::JsonValue;
+
+use crate::schema::{self, FieldKind, FieldSchema, SchemaRegistry, Value};
+use crate::wire::{WireFields, WireValue, MAX_FIELD_NUMBER};
+
+
+/// Predicate on the payload of a call, listed in the `message_rules` array
+/// of a destination
+///
+/// Every rule of a destination has to hold for the call to be allowed.
+/// Rules are written as JSON objects of the following forms:
+///
+/// * `{"max_size": 1024}`, `{"min_size": 1}` - payload length in bytes
+/// * `{"prefix": "GET "}`, `{"prefix_hex": "0a03"}` - leading bytes of the
+///   payload, as text or as hex digits
+/// * `{"field": "1.2", ...}` - a field of the payload decoded as Protobuf
+///   wire format, addressed by the field numbers of nested messages, with
+///   one of `"equals"` (string or number), `"min"`, `"max"` (numbers) or
+///   `"present"` (boolean)
+/// * `{"message": "payments.Refund"}` - the payload has to decode as the
+///   given message type from the descriptor sets loaded by the server, and
+///   the type URL of a typed payload has to name that type
+/// * `{"message": "payments.Refund", "condition": "amount < 1000"}` - in
+///   addition, a field of the decoded message, addressed by dotted field
+///   names, has to compare with a literal using one of `==`, `!=`, `<`,
+///   `<=`, `>`, `>=`. Literals are numbers, JSON strings, `true`, `false`
+///   or names of enum values, and missing fields have their default value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageRule {
+    MaxSize(usize),
+    MinSize(usize),
+    Prefix(Vec<u8>),
+    Field { path: Vec<u32>, condition: FieldCondition },
+    Typed { message: String, comparison: Option<Comparison> },
+}
+
+
+/// Comparison of a decoded field against a literal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Expression the comparison was parsed from
+    text: String,
+    path: Vec<FieldSchema>,
+    operator: Operator,
+    value: Value,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+
+/// Condition on a single decoded field
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldCondition {
+    Equals(FieldValue),
+    Min(u64),
+    Max(u64),
+    Present(bool),
+}
+
+
+/// Value of a decoded field as seen by message rules
+///
+/// Varint and fixed-size fields are numbers, length-delimited fields are
+/// raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Number(u64),
+    Bytes(Vec<u8>),
+}
+
+
+/// Reason a payload fails a message rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// Payload cannot be decoded the way the rule requires
+    Malformed(String),
+    /// Payload does not satisfy the rule
+    Disallowed(String),
+}
+
+
+impl fmt::Display for MessageRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageRule::MaxSize(size) => write!(f, "size <= {}", size),
+            MessageRule::MinSize(size) => write!(f, "size >= {}", size),
+            MessageRule::Prefix(prefix) => write!(f, "prefix {}", to_hex(prefix)),
+            MessageRule::Field { path, condition } => {
+                let path = format_path(path);
+                match condition {
+                    FieldCondition::Equals(value) => write!(f, "field {} == {}", path, value),
+                    FieldCondition::Min(min) => write!(f, "field {} >= {}", path, min),
+                    FieldCondition::Max(max) => write!(f, "field {} <= {}", path, max),
+                    FieldCondition::Present(true) => write!(f, "field {} present", path),
+                    FieldCondition::Present(false) => write!(f, "field {} absent", path),
+                }
+            },
+            MessageRule::Typed { message, comparison: None } => write!(f, "{}", message),
+            MessageRule::Typed { message, comparison: Some(comparison) } => {
+                write!(f, "{} where {}", message, comparison.text)
+            },
+        }
+    }
+}
+
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Number(number) => write!(f, "{}", number),
+            FieldValue::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => write!(f, "{:?}", text),
+                Err(_) => write!(f, "0x{}", to_hex(bytes)),
+            },
+        }
+    }
+}
+
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Malformed(reason) => write!(f, "{}", reason),
+            Violation::Disallowed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+
+impl MessageRule {
+    /// Parses a rule from its JSON object, resolving message types and
+    /// fields against the registry
+    pub fn from_json(
+        value: &JsonValue, schemas: &SchemaRegistry
+    ) -> Result<MessageRule, String> {
+        let object: &HashMap<String, JsonValue> = value.get()
+            .ok_or("message rule should be an object")?;
+
+        if let Some(message) = object.get("message") {
+            let message: &String = message.get()
+                .ok_or("\"message\" should be a fully-qualified message name")?;
+            if !schemas.has_message(message) {
+                return Err(format!(
+                    "unknown message type '{}', is its descriptor set loaded?", message));
+            }
+            if let Some(key) = object.keys().find(|key| *key != "message" && *key != "condition") {
+                return Err(format!("unexpected key \"{}\" in message type rule", key));
+            }
+            let comparison = match object.get("condition") {
+                Some(condition) => {
+                    let condition: &String = condition.get()
+                        .ok_or("\"condition\" should be a string")?;
+                    Some(parse_comparison(condition, message, schemas)?)
+                },
+                None => None,
+            };
+            return Ok(MessageRule::Typed { message: message.clone(), comparison });
+        }
+
+        if let Some(path) = object.get("field") {
+            let path = parse_path(path)?;
+            let mut conditions = object.iter().filter(|(key, _)| key.as_str() != "field");
+            let condition = match (conditions.next(), conditions.next()) {
+                (Some((key, value)), None) => parse_condition(key, value)?,
+                (None, _) => return Err(
+                    "field rule needs one of \"equals\", \"min\", \"max\" or \"present\"".to_owned()),
+                (Some(_), Some(_)) => return Err(
+                    "field rule should have a single condition".to_owned()),
+            };
+            return Ok(MessageRule::Field { path, condition });
+        }
+
+        let mut keys = object.iter();
+        let (key, value) = match (keys.next(), keys.next()) {
+            (Some(entry), None) => entry,
+            _ => return Err(
+                "message rule should have exactly one of \"max_size\", \"min_size\", \
+                \"prefix\", \"prefix_hex\" or \"field\"".to_owned()),
+        };
+        match key.as_str() {
+            "max_size" => Ok(MessageRule::MaxSize(parse_number(key, value)? as usize)),
+            "min_size" => Ok(MessageRule::MinSize(parse_number(key, value)? as usize)),
+            "prefix" => {
+                let prefix: &String = value.get()
+                    .ok_or("\"prefix\" should be a string")?;
+                Ok(MessageRule::Prefix(prefix.as_bytes().to_vec()))
+            },
+            "prefix_hex" => {
+                let prefix: &String = value.get()
+                    .ok_or("\"prefix_hex\" should be a string")?;
+                Ok(MessageRule::Prefix(from_hex(prefix)?))
+            },
+            other => Err(format!("unknown message rule \"{}\"", other)),
+        }
+    }
+
+    /// Checks the payload of a call against the rule
+    pub fn check(&self, payload: &[u8], schemas: &SchemaRegistry) -> Result<(), Violation> {
+        match self {
+            MessageRule::MaxSize(size) if payload.len() > *size => Err(Violation::Disallowed(
+                format!("payload of {} bytes is larger than {} bytes", payload.len(), size))),
+            MessageRule::MinSize(size) if payload.len() < *size => Err(Violation::Disallowed(
+                format!("payload of {} bytes is smaller than {} bytes", payload.len(), size))),
+            MessageRule::Prefix(prefix) if !payload.starts_with(prefix) => Err(
+                Violation::Disallowed(format!(
+                    "payload does not start with {}", to_hex(prefix)))),
+            MessageRule::Field { path, condition } => check_field(payload, path, condition),
+            MessageRule::Typed { message, comparison } => {
+                schemas.validate(message, payload).map_err(|e| Violation::Malformed(
+                    format!("payload is not a valid {}: {}", message, e)))?;
+                match comparison {
+                    Some(comparison) => comparison.check(payload),
+                    None => Ok(()),
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+
+impl Comparison {
+    fn check(&self, payload: &[u8]) -> Result<(), Violation> {
+        let actual = schema::decode(&self.path, payload).map_err(Violation::Malformed)?;
+        let holds = match (self.operator, actual.partial_cmp(&self.value)) {
+            (Operator::NotEqual, ordering) => ordering != Some(std::cmp::Ordering::Equal),
+            (_, None) => false,
+            (Operator::Equal, Some(ordering)) => ordering.is_eq(),
+            (Operator::Less, Some(ordering)) => ordering.is_lt(),
+            (Operator::LessOrEqual, Some(ordering)) => ordering.is_le(),
+            (Operator::Greater, Some(ordering)) => ordering.is_gt(),
+            (Operator::GreaterOrEqual, Some(ordering)) => ordering.is_ge(),
+        };
+        if holds {
+            Ok(())
+        } else {
+            Err(Violation::Disallowed(format!(
+                "{} is {}, which does not satisfy {}",
+                self.path.iter().map(|field| field.name.as_str()).collect::<Vec<_>>().join("."),
+                actual, self.text)))
+        }
+    }
+}
+
+
+/// Parses `<field path> <operator> <literal>`, checking that the literal
+/// suits the type of the field
+fn parse_comparison(
+    text: &str, message: &str, schemas: &SchemaRegistry
+) -> Result<Comparison, String> {
+    let text = text.trim();
+    let path_end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(text.len());
+    let (path, rest) = text.split_at(path_end);
+    if path.is_empty() {
+        return Err(format!("condition '{}' should start with a field path", text));
+    }
+    let rest = rest.trim_start();
+    let (operator, literal) = [
+        ("==", Operator::Equal), ("!=", Operator::NotEqual),
+        ("<=", Operator::LessOrEqual), (">=", Operator::GreaterOrEqual),
+        ("<", Operator::Less), (">", Operator::Greater),
+    ].iter()
+        .find_map(|(symbol, operator)| rest.strip_prefix(symbol).map(|literal| (*operator, literal)))
+        .ok_or_else(|| format!("condition '{}' has no comparison operator", text))?;
+    let literal = literal.trim();
+
+    let fields = schemas.resolve(message, path)?;
+    let field = fields.last().expect("Resolved paths are never empty");
+    let value = parse_literal(literal, field, schemas)
+        .map_err(|e| format!("condition '{}': {}", text, e))?;
+    let ordered = matches!(value, Value::Int(_) | Value::Float(_))
+        && !matches!(field.kind, FieldKind::Enum(_));
+    if !ordered && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+        return Err(format!(
+            "condition '{}': field '{}' of type {} supports only == and !=",
+            text, field.name, field.kind));
+    }
+    Ok(Comparison { text: text.to_owned(), path: fields, operator, value })
+}
+
+
+fn parse_literal(
+    literal: &str, field: &FieldSchema, schemas: &SchemaRegistry
+) -> Result<Value, String> {
+    let mismatch = || format!("'{}' is not a valid {} value", literal, field.kind);
+    match &field.kind {
+        FieldKind::Double | FieldKind::Float => {
+            literal.parse::<f64>().map(Value::Float).map_err(|_| mismatch())
+        },
+        FieldKind::Bool => match literal {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(mismatch()),
+        },
+        FieldKind::String | FieldKind::Bytes => {
+            let text: String = match literal.parse::<JsonValue>() {
+                Ok(JsonValue::String(text)) => text,
+                _ => return Err(format!("'{}' should be a quoted string", literal)),
+            };
+            Ok(match field.kind {
+                FieldKind::String => Value::Text(text),
+                _ => Value::Bytes(text.into_bytes()),
+            })
+        },
+        FieldKind::Enum(enumeration) => match literal.parse::<i128>() {
+            Ok(number) => Ok(Value::Int(number)),
+            Err(_) => schemas.enum_value(enumeration, literal)
+                .map(|number| Value::Int(i128::from(number)))
+                .ok_or_else(|| format!("'{}' is not a value of {}", literal, enumeration)),
+        },
+        FieldKind::Message(_) | FieldKind::Group => Err(format!(
+            "field '{}' of type {} cannot be compared", field.name, field.kind)),
+        _ => literal.parse::<i128>().map(Value::Int).map_err(|_| mismatch()),
+    }
+}
+
+
+fn check_field(
+    payload: &[u8], path: &[u32], condition: &FieldCondition
+) -> Result<(), Violation> {
+    let name = format_path(path);
+    let value = find_field(payload, path).map_err(Violation::Malformed)?;
+    let value = match (value, condition) {
+        (None, FieldCondition::Present(false)) => return Ok(()),
+        (Some(_), FieldCondition::Present(true)) => return Ok(()),
+        (Some(_), FieldCondition::Present(false)) => return Err(Violation::Disallowed(
+            format!("field {} should be absent", name))),
+        (None, _) => return Err(Violation::Disallowed(
+            format!("field {} is missing", name))),
+        (Some(value), _) => value,
+    };
+    let number = |value: &FieldValue| match value {
+        FieldValue::Number(number) => Ok(*number),
+        FieldValue::Bytes(_) => Err(Violation::Malformed(
+            format!("field {} is not a number", name))),
+    };
+    match condition {
+        FieldCondition::Equals(expected) => match (expected, &value) {
+            (FieldValue::Number(_), FieldValue::Bytes(_)) => Err(Violation::Malformed(
+                format!("field {} is not a number", name))),
+            (FieldValue::Bytes(_), FieldValue::Number(_)) => Err(Violation::Malformed(
+                format!("field {} is not a string", name))),
+            _ if *expected == value => Ok(()),
+            _ => Err(Violation::Disallowed(
+                format!("field {} is {}, expected {}", name, value, expected))),
+        },
+        FieldCondition::Min(min) => match number(&value)? {
+            actual if actual < *min => Err(Violation::Disallowed(
+                format!("field {} is {}, below minimum {}", name, actual, min))),
+            _ => Ok(()),
+        },
+        FieldCondition::Max(max) => match number(&value)? {
+            actual if actual > *max => Err(Violation::Disallowed(
+                format!("field {} is {}, above maximum {}", name, actual, max))),
+            _ => Ok(()),
+        },
+        FieldCondition::Present(_) => Ok(()),
+    }
+}
+
+
+/// Returns the last occurrence of the field at the given path, following
+/// Protobuf semantics for scalar fields which appear several times
+fn find_field(payload: &[u8], path: &[u32]) -> Result<Option<FieldValue>, String> {
+    let mut message = payload;
+    for (depth, number) in path.iter().enumerate() {
+        let mut found = None;
+        for field in WireFields::new(message) {
+            let (field_number, value) = field?;
+            if field_number == *number {
+                found = Some(value);
+            }
+        }
+        match found {
+            None => return Ok(None),
+            Some(value) if depth + 1 == path.len() => return Ok(Some(match value {
+                WireValue::Bytes(bytes) => FieldValue::Bytes(bytes.to_vec()),
+                WireValue::Varint(number) | WireValue::Fixed64(number) => {
+                    FieldValue::Number(number)
+                },
+                WireValue::Fixed32(number) => FieldValue::Number(u64::from(number)),
+            })),
+            Some(WireValue::Bytes(bytes)) => message = bytes,
+            Some(_) => return Err(format!(
+                "field {} is not a message", format_path(&path[..=depth]))),
+        }
+    }
+    Ok(None)
+}
+
+
+fn parse_path(value: &JsonValue) -> Result<Vec<u32>, String> {
+    let numbers: Vec<String> = match value {
+        JsonValue::Number(_) => vec![parse_number("field", value)?.to_string()],
+        JsonValue::String(path) => path.split('.').map(str::to_owned).collect(),
+        _ => return Err("\"field\" should be a field number or a dotted path".to_owned()),
+    };
+    numbers.iter()
+        .map(|number| match number.trim().parse::<u64>() {
+            Ok(number) if (1..=MAX_FIELD_NUMBER).contains(&number) => Ok(number as u32),
+            _ => Err(format!("invalid field number \"{}\"", number)),
+        })
+        .collect()
+}
+
+
+fn parse_condition(key: &str, value: &JsonValue) -> Result<FieldCondition, String> {
+    match key {
+        "equals" => match value {
+            JsonValue::String(text) => Ok(FieldCondition::Equals(
+                FieldValue::Bytes(text.as_bytes().to_vec()))),
+            JsonValue::Boolean(flag) => Ok(FieldCondition::Equals(
+                FieldValue::Number(u64::from(*flag)))),
+            _ => Ok(FieldCondition::Equals(FieldValue::Number(parse_number(key, value)?))),
+        },
+        "min" => Ok(FieldCondition::Min(parse_number(key, value)?)),
+        "max" => Ok(FieldCondition::Max(parse_number(key, value)?)),
+        "present" => match value {
+            JsonValue::Boolean(flag) => Ok(FieldCondition::Present(*flag)),
+            _ => Err("\"present\" should be a boolean".to_owned()),
+        },
+        other => Err(format!("unknown field condition \"{}\"", other)),
+    }
+}
+
+
+fn parse_number(key: &str, value: &JsonValue) -> Result<u64, String> {
+    match value {
+        JsonValue::Number(number) if *number >= 0.0 && number.fract() == 0.0
+            && *number < u64::MAX as f64 => Ok(*number as u64),
+        _ => Err(format!("\"{}\" should be a non-negative integer", key)),
+    }
+}
+
+
+fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err(format!("\"{}\" has an odd number of hex digits", text));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16)
+            .map_err(|_| format!("\"{}\" is not a hex string", text)))
+        .collect()
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+fn format_path(path: &[u32]) -> String {
+    path.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::message_rules::{MessageRule, Violation};
+    use crate::schema::{test_refund, test_registry};
+
+    fn check(json: &str, payload: &[u8]) -> Result<(), Violation> {
+        let registry = test_registry();
+        MessageRule::from_json(&json.parse().unwrap(), &registry)
+            .unwrap()
+            .check(payload, &registry)
+    }
+
+    /// Checks a condition on a `payments.Refund` message
+    fn check_refund(condition: &str, payload: &[u8]) -> Result<(), Violation> {
+        check(
+            &format!(r#"{{"message": "payments.Refund", "condition": {:?}}}"#, condition),
+            payload)
+    }
+
+    /// Message with `1: "Delete"`, `2: 1500` and `3: {1: 7}`
+    fn payload() -> Vec<u8> {
+        let mut payload = vec![0x0a, 0x06];
+        payload.extend_from_slice(b"Delete");
+        payload.extend_from_slice(&[0x10, 0xdc, 0x0b, 0x1a, 0x02, 0x08, 0x07]);
+        payload
+    }
+
+    fn is_disallowed(result: Result<(), Violation>) -> bool {
+        matches!(result, Err(Violation::Disallowed(_)))
+    }
+
+    fn is_malformed(result: Result<(), Violation>) -> bool {
+        matches!(result, Err(Violation::Malformed(_)))
+    }
+
+    #[test]
+    fn test_size_and_prefix() {
+        assert_eq!(check(r#"{"max_size": 4}"#, b"1234"), Ok(()));
+        assert!(is_disallowed(check(r#"{"max_size": 4}"#, b"12345")));
+        assert!(is_disallowed(check(r#"{"min_size": 1}"#, b"")));
+        assert_eq!(check(r#"{"prefix": "GET "}"#, b"GET /"), Ok(()));
+        assert!(is_disallowed(check(r#"{"prefix": "GET "}"#, b"PUT /")));
+        assert_eq!(check(r#"{"prefix_hex": "0a06"}"#, &payload()), Ok(()));
+    }
+
+    #[test]
+    fn test_fields() {
+        let payload = payload();
+        assert_eq!(check(r#"{"field": 1, "equals": "Delete"}"#, &payload), Ok(()));
+        assert!(is_disallowed(check(r#"{"field": 1, "equals": "Get"}"#, &payload)));
+        assert_eq!(check(r#"{"field": 2, "max": 2000}"#, &payload), Ok(()));
+        assert!(is_disallowed(check(r#"{"field": 2, "max": 1000}"#, &payload)));
+        assert!(is_disallowed(check(r#"{"field": 2, "min": 2000}"#, &payload)));
+        assert_eq!(check(r#"{"field": "3.1", "equals": 7}"#, &payload), Ok(()));
+        assert_eq!(check(r#"{"field": 4, "present": false}"#, &payload), Ok(()));
+        assert!(is_disallowed(check(r#"{"field": 4, "equals": 1}"#, &payload)));
+        assert!(is_disallowed(check(r#"{"field": "3.1", "present": false}"#, &payload)));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let rule = r#"{"field": 1, "present": true}"#;
+        assert!(is_malformed(check(rule, &[0x0a, 0x10, 0x01])));
+        assert!(is_malformed(check(rule, &[0xff])));
+        assert!(is_malformed(check(r#"{"field": "2.1", "present": true}"#, &payload())));
+    }
+
+    #[test]
+    fn test_typed() {
+        let refund = test_refund();
+        assert_eq!(check(r#"{"message": "payments.Refund"}"#, &refund), Ok(()));
+        assert_eq!(check_refund("amount < 2000", &refund), Ok(()));
+        assert!(is_disallowed(check_refund("amount < 1000", &refund)));
+        assert_eq!(check_refund("method == \"Delete\"", &refund), Ok(()));
+        assert!(is_disallowed(check_refund("method != \"Delete\"", &refund)));
+        assert_eq!(check_refund("header.priority >= -2", &refund), Ok(()));
+        assert_eq!(check_refund("kind == PARTIAL", &refund), Ok(()));
+        assert!(is_disallowed(check_refund("kind == FULL", &refund)));
+        assert_eq!(check_refund("ratio <= 0.5", &refund), Ok(()));
+
+        let error = check_refund("amount < 1000", &refund).unwrap_err();
+        assert!(error.to_string().contains("1500"), "{}", error);
+        assert!(is_malformed(check_refund("amount < 1000", &[0x12, 0x01, 0x00])));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for json in [
+            r#"{}"#,
+            r#"{"max_size": -1}"#,
+            r#"{"max_size": 1, "min_size": 0}"#,
+            r#"{"prefix_hex": "0g"}"#,
+            r#"{"field": 0, "present": true}"#,
+            r#"{"field": 1}"#,
+            r#"{"field": 1, "min": 1, "max": 2}"#,
+            r#"{"field": "1.x", "present": true}"#,
+            r#"{"size": 1}"#,
+            r#"{"message": "payments.Missing"}"#,
+            r#"{"message": "payments.Refund", "condition": "missing == 1"}"#,
+            r#"{"message": "payments.Refund", "condition": "amount"}"#,
+            r#"{"message": "payments.Refund", "condition": "amount < many"}"#,
+            r#"{"message": "payments.Refund", "condition": "method < \"Delete\""}"#,
+            r#"{"message": "payments.Refund", "condition": "method == Delete"}"#,
+            r#"{"message": "payments.Refund", "condition": "kind == EVERYTHING"}"#,
+            r#"{"message": "payments.Refund", "condition": "kind < FULL"}"#,
+            r#"{"message": "payments.Refund", "condition": "header == 1"}"#,
+            r#"{"message": "payments.Refund", "condition": "amount < 1", "field": 1}"#,
+        ] {
+            let rule = MessageRule::from_json(&json.parse().unwrap(), &test_registry());
+            assert!(rule.is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexSet};
+use tinyjson::JsonValue;
+
+
+/// Characters which turn a name into a glob pattern
+const GLOB_CHARACTERS: &[char] = &['*', '?', '[', '{'];
+
+
+/// Pattern of identifier names a source or destination entry applies to
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Exact(String),
+    /// Glob such as `payments-*` or `*.internal`, where `*` also matches
+    /// dots and slashes
+    Glob(Glob),
+    /// Regular expression which has to match the whole name
+    Regex(String),
+    /// Any name, for entries which select identities by other means
+    Any,
+}
+
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NamePattern::Exact(name) => write!(f, "name '{}'", name),
+            NamePattern::Glob(glob) => write!(f, "glob '{}'", glob.glob()),
+            NamePattern::Regex(regex) => write!(f, "regex '{}'", regex),
+            NamePattern::Any => write!(f, "any name"),
+        }
+    }
+}
+
+
+impl NamePattern {
+    /// Parses the pattern of a source or destination object, given either by
+    /// `name`, which is a glob if it contains any of `*?[{`, or by
+    /// `name_regex`
+    pub fn from_json(object: &JsonValue) -> Result<NamePattern, String> {
+        let field = |key| object.get::<HashMap<String, JsonValue>>()
+            .and_then(|object| object.get(key))
+            .map(|value| value.get::<String>()
+                .ok_or_else(|| format!("'{}' should be a string", key)));
+        match (field("name").transpose()?, field("name_regex").transpose()?) {
+            (Some(_), Some(_)) => Err("only one of 'name' and 'name_regex' may be given".to_owned()),
+            (None, None) => Err("either 'name' or 'name_regex' should be given".to_owned()),
+            (Some(name), None) => NamePattern::from_name(name),
+            (None, Some(regex)) => match Regex::new(&anchored(regex)) {
+                Ok(_) => Ok(NamePattern::Regex(regex.clone())),
+                Err(e) => Err(format!("invalid regex '{}': {}", regex, e)),
+            },
+        }
+    }
+
+    /// Parses a name, which is a glob if it contains any of `*?[{`
+    pub fn from_name(name: &str) -> Result<NamePattern, String> {
+        if name.contains(GLOB_CHARACTERS) {
+            GlobBuilder::new(name)
+                .literal_separator(false)
+                .build()
+                .map(NamePattern::Glob)
+                .map_err(|e| format!("invalid glob '{}': {}", name, e.kind()))
+        } else {
+            Ok(NamePattern::Exact(name.to_owned()))
+        }
+    }
+}
+
+
+fn anchored(regex: &str) -> String {
+    format!("^(?:{})$", regex)
+}
+
+
+/// Index of name patterns, which looks a name up in all of them at once
+///
+/// Exact names are kept in a hash map, while globs and regexes are compiled
+/// into a `GlobSet` and a `RegexSet`, so a lookup does not walk the patterns
+/// one by one.
+#[derive(Debug)]
+pub struct NameIndex {
+    exact: HashMap<String, Vec<usize>>,
+    globs: GlobSet,
+    /// Position of each glob of `globs` among the indexed patterns
+    glob_positions: Vec<usize>,
+    regexes: RegexSet,
+    /// Position of each regex of `regexes` among the indexed patterns
+    regex_positions: Vec<usize>,
+    /// Positions of the patterns matching any name
+    any: Vec<usize>,
+}
+
+
+impl NameIndex {
+    pub fn new<'a, I>(patterns: I) -> NameIndex
+    where I: IntoIterator<Item = &'a NamePattern>
+    {
+        let mut exact = HashMap::<String, Vec<usize>>::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut glob_positions = Vec::new();
+        let mut regexes = Vec::new();
+        let mut regex_positions = Vec::new();
+        let mut any = Vec::new();
+        for (position, pattern) in patterns.into_iter().enumerate() {
+            match pattern {
+                NamePattern::Exact(name) => exact.entry(name.clone()).or_default().push(position),
+                NamePattern::Glob(glob) => {
+                    globs.add(glob.clone());
+                    glob_positions.push(position);
+                },
+                NamePattern::Regex(regex) => {
+                    regexes.push(anchored(regex));
+                    regex_positions.push(position);
+                },
+                NamePattern::Any => any.push(position),
+            }
+        }
+        NameIndex {
+            exact,
+            // Patterns were validated when parsed
+            globs: globs.build().expect("Globs should be valid"),
+            glob_positions,
+            regexes: RegexSet::new(regexes).expect("Regexes should be valid"),
+            regex_positions,
+            any,
+        }
+    }
+
+    /// Returns positions of the patterns matching the name, exact names
+    /// first, then globs, regexes and patterns matching any name, each kind
+    /// in the indexed order
+    pub fn lookup(&self, name: &str) -> Vec<usize> {
+        let mut positions = self.exact.get(name).cloned().unwrap_or_default();
+        positions.extend(self.globs.matches(name).into_iter().map(|i| self.glob_positions[i]));
+        positions.extend(self.regexes.matches(name).into_iter().map(|i| self.regex_positions[i]));
+        positions.extend(&self.any);
+        positions
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::name_index::{NameIndex, NamePattern};
+
+    fn pattern(json: &str) -> Result<NamePattern, String> {
+        NamePattern::from_json(&json.parse().unwrap())
+    }
+
+    #[test]
+    fn test_lookup() {
+        let patterns: Vec<_> = [
+            r#"{"name_regex": "payments-[0-9]+"}"#,
+            r#"{"name": "*.internal"}"#,
+            r#"{"name": "payments-1"}"#,
+            r#"{"name": "payments-*"}"#,
+            r#"{"name": "payments-1"}"#,
+        ].iter().map(|json| pattern(json).unwrap()).collect();
+        let index = NameIndex::new(&patterns);
+
+        assert_eq!(index.lookup("payments-1"), vec![2, 4, 3, 0]);
+        assert_eq!(index.lookup("payments-api"), vec![3]);
+        assert_eq!(index.lookup("db.eu.internal"), vec![1]);
+        assert!(index.lookup("xpayments-1").is_empty());
+        assert!(index.lookup("internal").is_empty());
+        assert!(NameIndex::new(&[]).lookup("").is_empty());
+
+        let index = NameIndex::new(&[NamePattern::Any, patterns[2].clone()]);
+        assert_eq!(index.lookup("payments-1"), vec![1, 0]);
+        assert_eq!(index.lookup(""), vec![0]);
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        for json in [
+            r#"{}"#,
+            r#"{"name": 1}"#,
+            r#"{"name": "a", "name_regex": "a"}"#,
+            r#"{"name": "a[b"}"#,
+            r#"{"name_regex": "a("}"#,
+        ] {
+            assert!(pattern(json).is_err(), "{}", json);
+        }
+        assert_eq!(pattern(r#"{"name": "a.b"}"#).unwrap().to_string(), "name 'a.b'");
+    }
+}
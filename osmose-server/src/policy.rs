@@ -0,0 +1,733 @@
+use std::collections::HashMap;
+
+use tinyjson::JsonValue;
+
+
+/// Words which have to be quoted to be used as names
+const KEYWORDS: &[&str] = &[
+    "allow", "deny", "group", "when", "and", "as", "pid", "labels", "regex",
+];
+
+/// Punctuation, longer symbols first
+const SYMBOLS: &[&str] = &["->", "==", "!=", "<=", ">=", "<", ">", "=", ",", "(", ")", "@"];
+
+
+/// Positions of the values of compiled rules, by their JSON paths
+#[derive(Debug, Default)]
+pub struct Locations(HashMap<String, Location>);
+
+
+/// Line and column in the policy text, starting from 1
+pub type Location = (usize, usize);
+
+
+impl Locations {
+    /// Finds the position of the value at the JSON path, or of the closest
+    /// value containing it
+    pub fn locate(&self, path: &str) -> Option<Location> {
+        let mut path = path;
+        loop {
+            if let Some(location) = self.0.get(path) {
+                return Some(*location);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+
+    fn insert(&mut self, path: String, location: Location) {
+        self.0.entry(path).or_insert(location);
+    }
+}
+
+
+/// Compiles a policy into the JSON model of the rules
+///
+/// A policy is a list of statements, where line breaks are not significant
+/// and `#` starts a comment running to the end of the line:
+///
+/// * `allow a, b -> c, d` - each source may call each destination
+/// * `deny a -> c` - the call is refused whatever other rules allow
+/// * `group frontend = web-*, mobile-*` - a group of members, used by
+///   `@frontend` in place of a source or destination
+/// * `include "payments.policy"` - includes another rules file or directory
+///
+/// Sources and destinations are names, globs such as `payments-*`, quoted
+/// names, `regex "batch-[0-9]+"` or `@group`, optionally followed by
+/// `pid 111`, `pid (111, 112)` or `pid any` and by `labels "env=prod"`. A
+/// member may also consist of `labels` alone to match any name.
+///
+/// An allow statement may end with `when` and conditions joined by `and`:
+///
+/// * `payload.size < 4k` - payload length compared with `<`, `<=`, `>`,
+///   `>=` or `==`, where `k` and `m` stand for KiB and MiB
+/// * `payload.prefix == "GET "`, `payload.prefix == 0x0a03`
+/// * `payload.type == "type.googleapis.com/x.Y"`, `payload.type in ("a", "b")`
+/// * `field 1.2 == 5`, `field 1 == "text"`, `field 1 >= 10`, `field 1 present`,
+///   `field 1 absent` - a field of the payload decoded as Protobuf wire format
+/// * `payload is payments.Refund`, `payload is payments.Refund where amount < 1000`
+///
+/// Rules are identified by `line N` of their statement unless the statement
+/// ends with `as "name"`. Every statement is compiled into entries of its
+/// own, one for each source, and the entries of statements repeating a
+/// source are merged when the rules are loaded, whatever the duplicate
+/// sources policy, with the merges listed in the load report.
+pub fn compile(text: &str) -> Result<(JsonValue, Locations), String> {
+    let mut compiler = Compiler {
+        tokens: tokenize(text)?,
+        position: 0,
+        groups: HashMap::new(),
+        group_locations: HashMap::new(),
+        rules: Vec::new(),
+        includes: Vec::new(),
+        locations: Locations::default(),
+    };
+    while compiler.peek() != &Token::End {
+        compiler.statement()?;
+    }
+
+    let rules = compiler.rules.into_iter()
+        .map(|(source, destinations)| {
+            let mut rule = HashMap::new();
+            rule.insert("source".to_owned(), source);
+            rule.insert("destinations".to_owned(), JsonValue::Array(destinations));
+            JsonValue::Object(rule)
+        })
+        .collect();
+    let mut policy = HashMap::new();
+    policy.insert("groups".to_owned(), JsonValue::Object(compiler.groups));
+    policy.insert("rules".to_owned(), JsonValue::Array(rules));
+    if !compiler.includes.is_empty() {
+        policy.insert("include".to_owned(), JsonValue::Array(compiler.includes));
+    }
+    Ok((JsonValue::Object(policy), compiler.locations))
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// Quoted string
+    Text(String),
+    Symbol(&'static str),
+    End,
+}
+
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Text(text) => format!("{:?}", text),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+            Token::End => "end of policy".to_owned(),
+        }
+    }
+}
+
+
+fn is_word_character(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.-*?/:[]".contains(c)
+}
+
+
+fn tokenize(text: &str) -> Result<Vec<(Token, Location)>, String> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let location = (line, text[line_start..i].chars().count() + 1);
+        let token = if c == '\n' {
+            line += 1;
+            line_start = i + 1;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        } else if c == '#' {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if c == '"' {
+            let (text, length) = quoted(rest).map_err(|e| format!(
+                "{} at line {} column {}", e, location.0, location.1))?;
+            i += length;
+            Token::Text(text)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            i += symbol.len();
+            Token::Symbol(symbol)
+        } else if is_word_character(c) {
+            let word = &rest[..rest.find(|c| !is_word_character(c)).unwrap_or(rest.len())];
+            // Arrows need no spaces around them
+            let word = &word[..word.find("->").unwrap_or(word.len())];
+            i += word.len();
+            Token::Word(word.to_owned())
+        } else {
+            return Err(format!(
+                "unexpected character '{}' at line {} column {}", c, location.0, location.1));
+        };
+        tokens.push((token, location));
+    }
+    tokens.push((Token::End, (line, text[line_start..].chars().count() + 1)));
+    Ok(tokens)
+}
+
+
+/// Reads a quoted string at the start of the text, returning it with the
+/// length of its quoted form
+fn quoted(text: &str) -> Result<(String, usize), &'static str> {
+    let mut value = String::new();
+    let mut characters = text.char_indices().skip(1);
+    while let Some((i, c)) = characters.next() {
+        match c {
+            '"' => return Ok((value, i + 1)),
+            '\n' => break,
+            '\\' => value.push(match characters.next() {
+                Some((_, '"')) => '"',
+                Some((_, '\\')) => '\\',
+                Some((_, 'n')) => '\n',
+                Some((_, 'r')) => '\r',
+                Some((_, 't')) => '\t',
+                _ => return Err("invalid escape sequence in string"),
+            }),
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string")
+}
+
+
+/// Source or destination with positions of its keys
+struct Member {
+    object: HashMap<String, JsonValue>,
+    locations: Vec<(String, Location)>,
+}
+
+
+impl Member {
+    fn record(&self, locations: &mut Locations, path: &str) {
+        for (key, location) in &self.locations {
+            let path = if key.is_empty() { path.to_owned() } else { format!("{}.{}", path, key) };
+            locations.insert(path, *location);
+        }
+    }
+}
+
+
+/// Conditions of an allow statement
+#[derive(Default)]
+struct Conditions {
+    message_rules: Vec<(JsonValue, Location)>,
+    allowed_types: Option<(Vec<JsonValue>, Location)>,
+}
+
+
+struct Compiler {
+    tokens: Vec<(Token, Location)>,
+    position: usize,
+    groups: HashMap<String, JsonValue>,
+    group_locations: HashMap<String, Location>,
+    /// Sources with their destinations, in the order of first appearance
+    rules: Vec<(JsonValue, Vec<JsonValue>)>,
+    includes: Vec<JsonValue>,
+    locations: Locations,
+}
+
+
+impl Compiler {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn location(&self) -> Location {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error_at(&self, location: Location, message: &str) -> String {
+        format!("{} at line {} column {}", message, location.0, location.1)
+    }
+
+    fn error(&self, expected: &str) -> String {
+        self.error_at(
+            self.location(),
+            &format!("expected {}, found {}", expected, self.peek().describe()))
+    }
+
+    /// Consumes the symbol if it comes next
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek() == &Token::Symbol(SYMBOLS.iter().find(|s| **s == symbol).unwrap()) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the keyword if it comes next
+    fn eat_word(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Token::Word(word) if word == keyword) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) { Ok(()) } else { Err(self.error(&format!("'{}'", symbol))) }
+    }
+
+    fn name(&mut self, what: &str) -> Result<String, String> {
+        match self.peek().clone() {
+            Token::Word(word) if !KEYWORDS.contains(&word.as_str()) => {
+                self.advance();
+                Ok(word)
+            },
+            Token::Text(text) => {
+                self.advance();
+                Ok(text)
+            },
+            _ => Err(self.error(what)),
+        }
+    }
+
+    fn text(&mut self, what: &str) -> Result<String, String> {
+        match self.peek().clone() {
+            Token::Text(text) => {
+                self.advance();
+                Ok(text)
+            },
+            _ => Err(self.error(what)),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<u64, String> {
+        match self.peek() {
+            Token::Word(word) => match word.parse() {
+                Ok(number) => {
+                    self.advance();
+                    Ok(number)
+                },
+                Err(_) => Err(self.error(what)),
+            },
+            _ => Err(self.error(what)),
+        }
+    }
+
+    /// Reads a size in bytes, with an optional `k` or `m` suffix
+    fn size(&mut self) -> Result<u64, String> {
+        let word = match self.peek() {
+            Token::Word(word) => word.to_ascii_lowercase(),
+            _ => return Err(self.error("a size such as 4096 or 4k")),
+        };
+        let (digits, unit) = match word.strip_suffix('k') {
+            Some(digits) => (digits, 1 << 10),
+            None => match word.strip_suffix('m') {
+                Some(digits) => (digits, 1 << 20),
+                None => (word.as_str(), 1),
+            },
+        };
+        match digits.parse::<u64>().ok().and_then(|size| size.checked_mul(unit)) {
+            Some(size) => {
+                self.advance();
+                Ok(size)
+            },
+            None => Err(self.error("a size such as 4096 or 4k")),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<&'static str, String> {
+        for operator in ["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat(operator) {
+                return Ok(operator);
+            }
+        }
+        Err(self.error("a comparison"))
+    }
+
+    /// Converts a comparison with a number into inclusive bounds
+    fn bounds(
+        &self, operator: &str, number: u64, location: Location
+    ) -> Result<(Option<u64>, Option<u64>), String> {
+        match operator {
+            "==" => Ok((Some(number), Some(number))),
+            "<=" => Ok((None, Some(number))),
+            ">=" => Ok((Some(number), None)),
+            "<" if number > 0 => Ok((None, Some(number - 1))),
+            ">" => Ok((Some(number.saturating_add(1)), None)),
+            "<" => Err(self.error_at(location, "condition can never hold")),
+            _ => Err(self.error_at(location, &format!("'{}' cannot be used here", operator))),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let location = self.location();
+        if self.eat_word("allow") {
+            self.rule(false, location)
+        } else if self.eat_word("deny") {
+            self.rule(true, location)
+        } else if self.eat_word("group") {
+            self.group()
+        } else if self.eat_word("include") {
+            let location = self.location();
+            let include = self.text("quoted path")?;
+            self.locations.insert(format!("include[{}]", self.includes.len()), location);
+            self.includes.push(JsonValue::String(include));
+            Ok(())
+        } else {
+            Err(self.error("'allow', 'deny', 'group' or 'include'"))
+        }
+    }
+
+    fn group(&mut self) -> Result<(), String> {
+        let location = self.location();
+        let group = self.name("group name")?;
+        if let Some((line, _)) = self.group_locations.get(&group) {
+            return Err(self.error_at(
+                location, &format!("redefinition of group '{}' from line {}", group, line)));
+        }
+        self.expect("=")?;
+        let path = format!("groups.{}", group);
+        self.locations.insert(path.clone(), location);
+        let members = self.members()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, member)| {
+                member.record(&mut self.locations, &format!("{}[{}]", path, i));
+                match (member.object.len(), member.object.get("name")) {
+                    (1, Some(name)) => name.clone(),
+                    _ => JsonValue::Object(member.object),
+                }
+            })
+            .collect();
+        self.group_locations.insert(group.clone(), location);
+        self.groups.insert(group, JsonValue::Array(members));
+        Ok(())
+    }
+
+    fn rule(&mut self, deny: bool, location: Location) -> Result<(), String> {
+        let sources = self.members()?;
+        self.expect("->")?;
+        let destinations = self.members()?;
+        let mut conditions = Conditions::default();
+        if self.eat_word("when") {
+            self.condition(&mut conditions)?;
+            while self.eat_word("and") {
+                self.condition(&mut conditions)?;
+            }
+        }
+        let (id, id_location) = if self.eat_word("as") {
+            let id_location = self.location();
+            (self.text("rule id")?, Some(id_location))
+        } else {
+            (format!("line {}", location.0), None)
+        };
+
+        for source in sources {
+            self.rules.push((JsonValue::Object(source.object.clone()), Vec::new()));
+            let k = self.rules.len() - 1;
+            self.locations.insert(format!("rules[{}]", k), location);
+            source.record(&mut self.locations, &format!("rules[{}].source", k));
+            for destination in &destinations {
+                let path = format!("rules[{}].destinations[{}]", k, self.rules[k].1.len());
+                destination.record(&mut self.locations, &path);
+                let mut object = destination.object.clone();
+                if deny {
+                    object.insert("effect".to_owned(), JsonValue::String("deny".to_owned()));
+                }
+                object.insert("id".to_owned(), JsonValue::String(id.clone()));
+                if let Some(id_location) = id_location {
+                    self.locations.insert(format!("{}.id", path), id_location);
+                }
+                if !conditions.message_rules.is_empty() {
+                    for (m, (_, location)) in conditions.message_rules.iter().enumerate() {
+                        self.locations.insert(format!("{}.message_rules[{}]", path, m), *location);
+                    }
+                    object.insert("message_rules".to_owned(), JsonValue::Array(
+                        conditions.message_rules.iter().map(|(rule, _)| rule.clone()).collect()));
+                }
+                if let Some((types, location)) = &conditions.allowed_types {
+                    self.locations.insert(format!("{}.allowed_types", path), *location);
+                    object.insert("allowed_types".to_owned(), JsonValue::Array(types.clone()));
+                }
+                self.rules[k].1.push(JsonValue::Object(object));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a comma-separated list of sources or destinations
+    fn members(&mut self) -> Result<Vec<Member>, String> {
+        let mut members = vec![self.member()?];
+        while self.eat(",") {
+            members.push(self.member()?);
+        }
+        Ok(members)
+    }
+
+    fn member(&mut self) -> Result<Member, String> {
+        let location = self.location();
+        let mut member = Member {
+            object: HashMap::new(),
+            locations: vec![(String::new(), location)],
+        };
+        let mut insert = |key: &str, value, location| {
+            member.object.insert(key.to_owned(), value);
+            member.locations.push((key.to_owned(), location));
+        };
+        if self.eat("@") {
+            insert("group", JsonValue::String(self.name("group name")?), location);
+        } else if self.eat_word("regex") {
+            let regex_location = self.location();
+            insert("name_regex", JsonValue::String(self.text("quoted regex")?), regex_location);
+        } else if !matches!(self.peek(), Token::Word(word) if word == "labels") {
+            insert("name", JsonValue::String(self.name("name")?), location);
+        }
+        loop {
+            let location = self.location();
+            if self.eat_word("pid") {
+                insert("pid", self.pid()?, location);
+            } else if self.eat_word("labels") {
+                insert("labels", JsonValue::String(self.text("quoted label selector")?), location);
+            } else {
+                break;
+            }
+        }
+        Ok(member)
+    }
+
+    fn pid(&mut self) -> Result<JsonValue, String> {
+        if self.eat_word("any") {
+            return Ok(JsonValue::String("any".to_owned()));
+        }
+        if !self.eat("(") {
+            return Ok(JsonValue::Number(self.number("process id")? as f64));
+        }
+        let mut ids = vec![JsonValue::Number(self.number("process id")? as f64)];
+        while self.eat(",") {
+            ids.push(JsonValue::Number(self.number("process id")? as f64));
+        }
+        self.expect(")")?;
+        Ok(JsonValue::Array(ids))
+    }
+
+    fn condition(&mut self, conditions: &mut Conditions) -> Result<(), String> {
+        let location = self.location();
+        let subject = match self.peek() {
+            Token::Word(word) => word.clone(),
+            _ => return Err(self.error("a condition")),
+        };
+        let rule = |key: &str, value| {
+            let mut rule = HashMap::new();
+            rule.insert(key.to_owned(), value);
+            rule
+        };
+        let number = |number: u64| JsonValue::Number(number as f64);
+        self.advance();
+        match subject.as_str() {
+            "payload.size" => {
+                let operator = self.comparison()?;
+                let size = self.size()?;
+                let (min, max) = self.bounds(operator, size, location)?;
+                for (key, size) in [("min_size", min), ("max_size", max)] {
+                    if let Some(size) = size {
+                        let size = JsonValue::Object(rule(key, number(size)));
+                        conditions.message_rules.push((size, location));
+                    }
+                }
+            },
+            "payload.prefix" => {
+                self.expect("==")?;
+                let prefix = match self.advance() {
+                    Token::Text(text) => rule("prefix", JsonValue::String(text)),
+                    Token::Word(word) if word.starts_with("0x") => {
+                        rule("prefix_hex", JsonValue::String(word[2..].to_owned()))
+                    },
+                    _ => {
+                        self.position -= 1;
+                        return Err(self.error("quoted text or hex bytes such as 0x0a03"));
+                    },
+                };
+                conditions.message_rules.push((JsonValue::Object(prefix), location));
+            },
+            "payload.type" => {
+                if conditions.allowed_types.is_some() {
+                    return Err(self.error_at(location, "payload type is already restricted"));
+                }
+                let types = if self.eat("==") {
+                    vec![JsonValue::String(self.text("quoted type URL")?)]
+                } else if self.eat_word("in") {
+                    self.expect("(")?;
+                    let mut types = vec![JsonValue::String(self.text("quoted type URL")?)];
+                    while self.eat(",") {
+                        types.push(JsonValue::String(self.text("quoted type URL")?));
+                    }
+                    self.expect(")")?;
+                    types
+                } else {
+                    return Err(self.error("'==' or 'in'"));
+                };
+                conditions.allowed_types = Some((types, location));
+            },
+            "field" => {
+                let path = match self.advance() {
+                    Token::Word(path) => JsonValue::String(path),
+                    _ => {
+                        self.position -= 1;
+                        return Err(self.error("field number such as 1 or 1.2"));
+                    },
+                };
+                let mut field = |key: &str, value| {
+                    let mut rule = rule("field", path.clone());
+                    rule.insert(key.to_owned(), value);
+                    conditions.message_rules.push((JsonValue::Object(rule), location));
+                };
+                if self.eat_word("present") {
+                    field("present", JsonValue::Boolean(true));
+                } else if self.eat_word("absent") {
+                    field("present", JsonValue::Boolean(false));
+                } else {
+                    let operator = self.comparison()?;
+                    if let (Token::Text(text), "==") = (self.peek().clone(), operator) {
+                        self.advance();
+                        field("equals", JsonValue::String(text));
+                    } else {
+                        let value = self.number("number or quoted text")?;
+                        match self.bounds(operator, value, location)? {
+                            (Some(min), Some(max)) if min == max => field("equals", number(min)),
+                            (min, max) => {
+                                if let Some(min) = min {
+                                    field("min", number(min));
+                                }
+                                if let Some(max) = max {
+                                    field("max", number(max));
+                                }
+                            },
+                        }
+                    }
+                }
+            },
+            "payload" => {
+                if !self.eat_word("is") {
+                    return Err(self.error("'is'"));
+                }
+                let mut typed = rule("message", JsonValue::String(self.name("message type")?));
+                if self.eat_word("where") {
+                    let field = self.name("field name")?;
+                    let operator = self.comparison()?;
+                    let value = match self.advance() {
+                        Token::Word(word) => word,
+                        Token::Text(text) => JsonValue::String(text).stringify()
+                            .expect("Strings should be serializable"),
+                        _ => {
+                            self.position -= 1;
+                            return Err(self.error("value"));
+                        },
+                    };
+                    typed.insert(
+                        "condition".to_owned(),
+                        JsonValue::String(format!("{} {} {}", field, operator, value)));
+                }
+                conditions.message_rules.push((JsonValue::Object(typed), location));
+            },
+            _ => {
+                self.position -= 1;
+                return Err(self.error(
+                    "payload.size, payload.prefix, payload.type, field or payload is"));
+            },
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::compile;
+
+    #[test]
+    fn test_compile() {
+        let policy = r#"
+# Front ends
+group frontend = web-*, mobile-* pid any
+
+allow gateway pid (111, 112) -> process1, "process2" labels "env=prod"
+    when payload.size < 4k and payload.prefix == 0x0a03
+allow gateway pid (111, 112) -> @frontend as "gateway-frontend"
+deny regex "batch-[0-9]+"->secrets.internal
+allow labels "team=payments" -> ledger when field 1.2 >= 10 and payload is payments.Refund where reason == "late"
+"#;
+        let expected = r#"
+{
+    "groups": {"frontend": ["web-*", {"name": "mobile-*", "pid": "any"}]},
+    "rules": [
+        {
+            "source": {"name": "gateway", "pid": [111, 112]},
+            "destinations": [
+                {"name": "process1", "id": "line 5",
+                 "message_rules": [{"max_size": 4095}, {"prefix_hex": "0a03"}]},
+                {"name": "process2", "labels": "env=prod", "id": "line 5",
+                 "message_rules": [{"max_size": 4095}, {"prefix_hex": "0a03"}]}
+            ]
+        },
+        {
+            "source": {"name": "gateway", "pid": [111, 112]},
+            "destinations": [{"group": "frontend", "id": "gateway-frontend"}]
+        },
+        {
+            "source": {"name_regex": "batch-[0-9]+"},
+            "destinations": [{"name": "secrets.internal", "effect": "deny", "id": "line 8"}]
+        },
+        {
+            "source": {"labels": "team=payments"},
+            "destinations": [
+                {"name": "ledger", "id": "line 9", "message_rules": [
+                    {"field": "1.2", "min": 10},
+                    {"message": "payments.Refund", "condition": "reason == \"late\""}
+                ]}
+            ]
+        }
+    ]
+}
+"#;
+        let (rules, locations) = compile(policy).unwrap();
+        assert_eq!(rules, expected.parse().unwrap());
+        assert_eq!(locations.locate("rules[0].destinations[1].labels"), Some((5, 54)));
+        assert_eq!(locations.locate("rules[0].destinations[1]"), Some((5, 43)));
+        assert_eq!(locations.locate("rules[1].source"), Some((7, 7)));
+        assert_eq!(locations.locate("rules[3].destinations[0].message_rules[1]"), Some((9, 65)));
+        assert_eq!(locations.locate("groups.frontend[1].pid"), Some((3, 34)));
+        assert_eq!(locations.locate("rules[2].source.name_regex"), Some((8, 12)));
+        assert_eq!(locations.locate("groups"), None);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let cases = [
+            ("allow a b", "expected '->', found 'b' at line 1 column 9"),
+            ("allow a ->\n", "expected name, found end of policy at line 2 column 1"),
+            ("permit a -> b", "expected 'allow', 'deny', 'group' or 'include', found 'permit' at line 1 column 1"),
+            ("allow a -> b when payload.size < 4x",
+             "expected a size such as 4096 or 4k, found '4x' at line 1 column 34"),
+            ("allow a -> b when payload.size < 0", "condition can never hold at line 1 column 19"),
+            ("allow a -> b when field 1 != 2", "'!=' cannot be used here at line 1 column 19"),
+            ("allow a pid x -> b", "expected process id, found 'x' at line 1 column 13"),
+            ("allow a -> \"b", "unterminated string at line 1 column 12"),
+            ("allow a -> b; c", "unexpected character ';' at line 1 column 13"),
+            ("allow deny -> b", "expected name, found 'deny' at line 1 column 7"),
+            ("group g = a\ngroup g = b", "redefinition of group 'g' from line 1 at line 2 column 7"),
+        ];
+        for (policy, error) in cases.iter() {
+            assert_eq!(compile(policy).unwrap_err(), *error, "{}", policy);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::watch;
+
+use crate::rules_database::{LoadOptions, RulesDatabase};
+use crate::schema::SchemaRegistry;
+
+
+/// How often the rules file is checked for changes when watched
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Loads the rules file and the descriptor sets again whenever asked to,
+/// publishing the new rules to the servers or keeping the current ones if
+/// any of the files is invalid
+pub struct Reloader {
+    path: PathBuf,
+    descriptor_sets: Vec<PathBuf>,
+    options: LoadOptions,
+    rules: watch::Sender<Arc<RulesDatabase>>,
+}
+
+
+impl Reloader {
+    pub fn new(
+        path: &Path,
+        descriptor_sets: Vec<PathBuf>,
+        options: LoadOptions,
+        rules: watch::Sender<Arc<RulesDatabase>>,
+    ) -> Self {
+        Reloader { path: path.to_owned(), descriptor_sets, options, rules }
+    }
+
+    /// Loads the rules and swaps them in for the requests which follow
+    ///
+    /// Returns whether the rules were replaced.
+    pub async fn reload(&self) -> bool {
+        let path = self.path.clone();
+        let descriptor_sets = self.descriptor_sets.clone();
+        let options = self.options;
+        let loaded = tokio::task::spawn_blocking(move || {
+            let schemas = SchemaRegistry::load(descriptor_sets.iter().map(PathBuf::as_path))?;
+            RulesDatabase::load(&path, Arc::new(schemas), options).map_err(|e| e.to_string())
+        }).await;
+        match loaded {
+            Ok(Ok(rules)) => {
+                log::info!("Reloaded rules from {:?}: {}", self.path, rules.get_report());
+                self.rules.send_replace(Arc::new(rules));
+                true
+            },
+            Ok(Err(error)) => {
+                log::error!("Cannot reload rules, keeping the previous rules: {}", error);
+                false
+            },
+            Err(error) => {
+                log::error!("Loading rules failed, keeping the previous rules: {}", error);
+                false
+            },
+        }
+    }
+
+    /// Reloads the rules on every SIGHUP and, if `watch` is set, whenever
+    /// the modification time or size of the rules path, of any file the
+    /// rules were read from or of a descriptor set changes
+    pub async fn run(self, watch: bool) {
+        #[cfg(unix)]
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("Cannot handle SIGHUP");
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut version = self.version();
+        loop {
+            #[cfg(unix)]
+            let hangup = hangups.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+            tokio::select! {
+                _ = hangup => {
+                    log::info!("Received SIGHUP, reloading rules");
+                },
+                _ = interval.tick(), if watch => {
+                    let current = self.version();
+                    // A missing file is most likely being replaced right now
+                    if current.contains(&None) || current == version {
+                        continue;
+                    }
+                    log::info!("Rules at {:?} changed, reloading rules", self.path);
+                },
+            }
+            self.reload().await;
+            version = self.version();
+        }
+    }
+
+    /// Versions of the rules path, which changes along with the files of a
+    /// directory, of the files the current rules were read from and of the
+    /// descriptor sets
+    fn version(&self) -> Vec<Option<(SystemTime, u64)>> {
+        let rules = self.rules.borrow();
+        std::iter::once(&self.path)
+            .chain(&rules.get_report().files)
+            .chain(&self.descriptor_sets)
+            .map(|path| file_version(path))
+            .collect()
+    }
+}
+
+
+fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::watch;
+    use osmose_identifier::Identifier;
+    use osmose_generated::generated_proto::osmose::Decision;
+    use crate::reload::Reloader;
+    use crate::rules_database::{Call, RulesDatabase};
+
+    fn decision(rules: &RulesDatabase) -> Decision {
+        let from = Identifier::from_given("process1", 1);
+        let to = Identifier::from_given("process2", 2);
+        rules.is_call_allowed(&Call {
+            from: &from, to: &to, payload: b"", type_url: None, explain: false,
+        }).decision
+    }
+
+    /// Writes rules allowing `process1` to call the destination
+    fn write(path: &Path, destination: &str) {
+        std::fs::write(path, format!(
+            r#"[{{"source": {{"name": "process1"}}, "destinations": [{{"name": "{}"}}]}}]"#,
+            destination)).expect("Cannot write test configuration file");
+    }
+
+    fn channel(
+        path: &Path
+    ) -> (watch::Sender<Arc<RulesDatabase>>, watch::Receiver<Arc<RulesDatabase>>) {
+        watch::channel(Arc::new(
+            RulesDatabase::load(path, Default::default(), Default::default()).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let path = Path::new("test_reload_cfg.json");
+        write(path, "process2");
+        let (sender, receiver) = channel(path);
+        let reloader = Reloader::new(path, Vec::new(), Default::default(), sender);
+        assert_eq!(decision(&receiver.borrow()), Decision::ALLOW);
+
+        write(path, "process3");
+        assert!(reloader.reload().await);
+        assert_eq!(decision(&receiver.borrow()), Decision::DISALLOWED_DESTINATION);
+
+        std::fs::write(path, "[{").expect("Cannot write test configuration file");
+        assert!(!reloader.reload().await);
+        assert_eq!(decision(&receiver.borrow()), Decision::DISALLOWED_DESTINATION);
+
+        // Descriptor sets are loaded again along with the rules
+        write(path, "process2");
+        let descriptor_sets = vec!["test_reload_missing.desc".into()];
+        let (sender, receiver) = channel(path);
+        let reloader = Reloader::new(path, descriptor_sets, Default::default(), sender);
+        write(path, "process3");
+        assert!(!reloader.reload().await);
+        assert_eq!(decision(&receiver.borrow()), Decision::ALLOW);
+
+        std::fs::remove_file(path).expect("Cannot remove test configuration file");
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let path = Path::new("test_watch_cfg.json");
+        write(path, "process2");
+        let (sender, mut receiver) = channel(path);
+        let watcher = tokio::spawn(
+            Reloader::new(path, Vec::new(), Default::default(), sender).run(true));
+        // Lets the watcher take note of the current version of the file
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        write(path, "process33");
+        let changed = tokio::time::timeout(Duration::from_secs(5), receiver.changed()).await;
+        watcher.abort();
+        std::fs::remove_file(path).expect("Cannot remove test configuration file");
+        changed.expect("Changed rules should be reloaded").unwrap();
+        assert_eq!(decision(&receiver.borrow()), Decision::DISALLOWED_DESTINATION);
+    }
+}
@@ -1,74 +1,985 @@
 use osmose_identifier::Identifier;
 
 use std::string::String;
-use std::fs::File;
-use std::io::Read;
 
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use log;
+use osmose_generated::generated_proto::osmose::Decision as Decision;
 
-use tinyjson;
+use crate::labels::LabelSelector;
+use crate::message_rules::{MessageRule, Violation};
+use crate::name_index::{NameIndex, NamePattern};
+use crate::rules_files::{self, Document};
+use crate::rules_format::RulesFormat;
+use crate::schema::SchemaRegistry;
 
-use osmose_generated::generated_proto::osmose::Decision as Decision;
+/// Outcome of evaluating a single call against the rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub decision: Decision,
+    /// Human-readable explanation of the decision
+    pub reason: String,
+    /// Identifier of the rule which allowed the call, if any
+    pub matched_rule: Option<String>,
+    /// Evaluation steps, filled in only when explanation was requested
+    pub trace: Vec<String>,
+}
+
+/// A single call to be evaluated against the rules
+#[derive(Debug, Clone, Copy)]
+pub struct Call<'a> {
+    /// Identifier of the calling entity
+    pub from: &'a Identifier,
+    /// Identifier of the called entity
+    pub to: &'a Identifier,
+    /// Message passed with the call
+    pub payload: &'a [u8],
+    /// Type URL of the payload, if it was sent as `google.protobuf.Any`
+    pub type_url: Option<&'a str>,
+    /// Whether to record the evaluation trace
+    pub explain: bool,
+}
+
+/// Invalid rules file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulesError {
+    /// Rules file the error was found in
+    pub file: PathBuf,
+    /// JSON path of the invalid value, such as `[0].destinations[1].name`,
+    /// or its line and column for formats which keep them, empty if the
+    /// error concerns the whole file
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.file.display(), self.reason)
+        } else {
+            write!(f, "{}: {}: {}", self.file.display(), self.path, self.reason)
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+/// How to load entries which repeat the source of an earlier entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Refuse to load the rules
+    Error,
+    /// Add the destinations of the duplicate to the first entry
+    Merge,
+    /// Replace the earlier entry with the duplicate, with a warning for each
+    /// overwritten source
+    LastWins,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "error" => Ok(DuplicatePolicy::Error),
+            "merge" => Ok(DuplicatePolicy::Merge),
+            "last-wins" => Ok(DuplicatePolicy::LastWins),
+            _ => Err(format!(
+                "unknown duplicate policy '{}', expected error, merge or last-wins", policy)),
+        }
+    }
+}
+
+/// Settings of loading a rules file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadOptions {
+    /// Policy for all the duplicate sources, if given; otherwise the last
+    /// entry of a file wins, as rules files were always loaded, while the
+    /// entries of different files are merged
+    pub duplicates: Option<DuplicatePolicy>,
+    /// Format of the file, guessed by its extension if not given
+    pub format: Option<RulesFormat>,
+    /// Whether entries apply only to the ids given by their `pid`, which is
+    /// not checked otherwise
+    pub match_pid: bool,
+}
+
+/// What happened to an entry repeating the source of an earlier one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Merged,
+    Overwritten,
+}
+
+/// Entry which repeats the source of an earlier entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    /// Description of the repeated source
+    pub source: String,
+    /// Position of the duplicate entry, or its JSON path
+    pub path: String,
+    /// Position or JSON path of the entry it was merged into or overwrote
+    pub first: String,
+    pub resolution: Resolution,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.resolution {
+            Resolution::Merged => write!(
+                f, "source {} of {} merged into {}", self.source, self.path, self.first),
+            Resolution::Overwritten => write!(
+                f, "source {} of {} overwrote {}", self.source, self.path, self.first),
+        }
+    }
+}
+
+/// Summary of a loaded rules file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadReport {
+    /// Number of source entries in effect
+    pub sources: usize,
+    /// Number of destination entries in effect
+    pub destinations: usize,
+    pub duplicates: Vec<Duplicate>,
+    /// Files the rules were read from
+    pub files: Vec<PathBuf>,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{} source entries with {} destination entries",
+            self.sources, self.destinations)?;
+        if self.files.len() > 1 {
+            write!(f, " from {} files", self.files.len())?;
+        }
+        let count = |resolution| self.duplicates.iter()
+            .filter(|duplicate| duplicate.resolution == resolution)
+            .count();
+        match (count(Resolution::Merged), count(Resolution::Overwritten)) {
+            (0, 0) => Ok(()),
+            (merged, overwritten) => write!(
+                f, ", {} duplicate sources merged, {} overwritten", merged, overwritten),
+        }
+    }
+}
+
+/// Identifiers of the instances a rule applies to, given by `pid`
+#[derive(Debug, Clone, PartialEq)]
+enum PidMatch {
+    Any,
+    OneOf(Vec<u64>),
+}
+
+impl PidMatch {
+    fn matches(&self, id: u64) -> bool {
+        match self {
+            PidMatch::Any => true,
+            PidMatch::OneOf(ids) => ids.contains(&id),
+        }
+    }
+}
+
+impl fmt::Display for PidMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PidMatch::Any => write!(f, "any pid"),
+            PidMatch::OneOf(ids) if ids.len() == 1 => write!(f, "pid {}", ids[0]),
+            PidMatch::OneOf(ids) => write!(f, "pid one of {:?}", ids),
+        }
+    }
+}
+
+/// Largest id which JSON numbers represent exactly
+const MAX_SAFE_ID: f64 = 9_007_199_254_740_991.0;
+
+/// Identities a source or destination entry applies to, given either
+/// directly or as a member of a group
+#[derive(Debug, Clone)]
+struct Member {
+    pattern: NamePattern,
+    pid: PidMatch,
+    /// Labels the identities should have
+    labels: Option<LabelSelector>,
+    /// Group the identities were taken from
+    group: Option<String>,
+}
+
+impl Member {
+    /// Explains why the identifier is not a member despite its name matching
+    /// the pattern, if it is not
+    fn mismatch(&self, identifier: &Identifier) -> Option<String> {
+        if !self.pid.matches(identifier.get_id()) {
+            return Some(format!("id {} does not match {}", identifier.get_id(), self.pid));
+        }
+        match &self.labels {
+            Some(labels) if !labels.matches(identifier.get_labels()) => {
+                let labels_text: Vec<_> = identifier.get_labels()
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                Some(format!(
+                    "labels {{{}}} do not match selector {}", labels_text.join(", "), labels))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if let Some(labels) = &self.labels {
+            write!(f, " with labels {}", labels)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, " of group '{}'", group)?;
+        }
+        Ok(())
+    }
+}
+
+/// Index of the members of many entries
+#[derive(Debug)]
+struct MemberIndex {
+    index: NameIndex,
+    /// Entry and member position of each indexed pattern
+    positions: Vec<(usize, usize)>,
+}
+
+impl MemberIndex {
+    fn new<'a, I>(entries: I) -> MemberIndex
+    where I: IntoIterator<Item = &'a [Member]>
+    {
+        let members: Vec<_> = entries.into_iter()
+            .enumerate()
+            .flat_map(|(i, members)| members.iter().enumerate().map(move |(j, m)| (i, j, m)))
+            .collect();
+        MemberIndex {
+            index: NameIndex::new(members.iter().map(|(_, _, member)| &member.pattern)),
+            positions: members.iter().map(|&(i, j, _)| (i, j)).collect(),
+        }
+    }
+
+    /// Returns entry and member positions of the members matching the name,
+    /// in the order of `NameIndex::lookup`
+    fn lookup(&self, name: &str) -> Vec<(usize, usize)> {
+        self.index.lookup(name).into_iter().map(|k| self.positions[k]).collect()
+    }
+}
 
+#[derive(Debug)]
+struct SourceEntry {
+    rule: String,
+    /// Sources the entry applies to
+    members: Vec<Member>,
+    destinations: Vec<DestinationEntry>,
+    /// Index of the members of `destinations`
+    destination_index: MemberIndex,
+}
+
+#[derive(Debug)]
+struct DestinationEntry {
+    rule: String,
+    /// Destinations which may be called
+    members: Vec<Member>,
+    /// Whether the entry denies the calls instead of allowing them
+    deny: bool,
+    /// Type URLs or message names of the payloads the destination accepts,
+    /// any payload is accepted if not given
+    allowed_types: Option<Vec<String>>,
+    /// Predicates every payload sent to the destination has to satisfy
+    message_rules: Vec<MessageRule>,
+}
+
+/// Rules deciding which calls are allowed
+///
+/// Sources and destinations are given by exact names, globs or regexes, and
+/// destination entries either allow or, with `"effect": "deny"`, deny calls.
+/// A call matched by any deny entry is refused, whatever allows it. Otherwise,
+/// when several allow entries match a call, the first (source, destination)
+/// pair in the following order decides: sources by kind of their pattern
+/// (exact names, then globs, then regexes, then entries without a name) and
+/// then by position in the file, destinations of each source in the same
+/// way. Calls matched by no entry are refused.
+///
+/// Sources and destinations may also be selected by the labels of their
+/// identifiers, with or without a name. Instead of either, a source or
+/// destination may reference a group of identities defined in the `groups`
+/// object of the rules file; such an entry applies to every member of the
+/// group.
 #[derive(Debug)]
 pub struct RulesDatabase {
-    db: HashMap<String, HashSet<String>>,
+    sources: Vec<SourceEntry>,
+    /// Index of the members of `sources`
+    source_index: MemberIndex,
+    /// Message types referenced by message rules
+    schemas: Arc<SchemaRegistry>,
+    report: LoadReport,
 }
 
 impl RulesDatabase {
-    pub fn new(path: &std::path::Path) -> RulesDatabase {
-        let path = path.canonicalize().unwrap();
-        let mut file = File::open(&path).unwrap();
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
-
-        log::info!{"Use rules file: {:?}", &path};
-
-        let mut d = HashMap::<String, HashSet<String>>::new();
-
-        let rules: tinyjson::JsonValue = data.parse().unwrap();
-
-        let arr: &Vec<_> = rules.get().expect("Array value");
-        for entry in arr.iter() {
-            let source_name: &String = entry["source"]["name"].get().unwrap();
-            let destinations: &Vec<_> = entry["destinations"]
-                .get()
-                .expect("Destinations should be an array");
-            let destinations_set: HashSet<String> = destinations
-                .iter()
-                .map(|x| x["name"].get::<String>().unwrap().to_string())
-                .collect();
+    /// Loads rules from a JSON, YAML, TOML or policy file, or from all such
+    /// files of a directory, resolving the message types used by message
+    /// rules against the registry
+    ///
+    /// Rules of several files are merged as if they were listed in a single
+    /// file in the order the files are read, see `rules_files::read`, where
+    /// the entries of a source repeated by several files are merged unless
+    /// the options give a duplicate sources policy. A group may be defined by
+    /// a single file only.
+    ///
+    /// Duplicate sources which were merged or overwritten are logged and
+    /// listed in the report of the database.
+    pub fn load(
+        path: &Path, schemas: Arc<SchemaRegistry>, options: LoadOptions
+    ) -> Result<RulesDatabase, RulesError> {
+        let documents = rules_files::read(path, options.format)?;
+        let root = documents[0].file.parent().unwrap_or_else(|| Path::new("/"));
+        let root = match path.is_dir() {
+            true => path.canonicalize().unwrap_or_else(|_| root.to_owned()),
+            false => root.to_owned(),
+        };
+        let mut parser = Parser {
+            file: &documents[0].file,
+            rule_prefix: String::new(),
+            schemas: &schemas,
+            groups: HashMap::new(),
+            group_files: HashMap::new(),
+            duplicates: options.duplicates,
+            match_pid: options.match_pid,
+        };
+        let (sources, duplicates) = parser.rules(&documents, &root)?;
 
-            d.insert(source_name.to_string(), destinations_set);
+        let report = LoadReport {
+            sources: sources.len(),
+            destinations: sources.iter().map(|s| s.destinations.len()).sum(),
+            duplicates,
+            files: documents.iter().map(|document| document.file.clone()).collect(),
+        };
+        for duplicate in &report.duplicates {
+            match duplicate.resolution {
+                Resolution::Merged => log::info!("{:?}: {}", path, duplicate),
+                Resolution::Overwritten => log::warn!("{:?}: {}", path, duplicate),
+            }
         }
 
-        return RulesDatabase { db: d };
+        let source_index = MemberIndex::new(sources.iter().map(|s| s.members.as_slice()));
+        Ok(RulesDatabase { sources, source_index, schemas, report })
+    }
+
+    /// Summary of loading the rules file
+    pub fn get_report(&self) -> &LoadReport {
+        &self.report
     }
 
-    pub fn is_call_allowed(&self, from: &Identifier, to: &Identifier) -> Decision {
-        match self.db.get(from.get_name()) {
-            Some(source) => {
-                if source.contains(to.get_name()) {
-                    Decision::ALLOW
-                } else {
-                    Decision::DISALLOWED_DESTINATION
+    /// Evaluates a single call and explains the decision
+    pub fn is_call_allowed(&self, call: &Call) -> Evaluation {
+        self.evaluate(&self.source_index.lookup(call.from.get_name()), call)
+    }
+
+    /// Evaluates many calls at once, returning evaluations in the same order
+    ///
+    /// Consecutive calls from the same source, which is the usual shape of a
+    /// fan-out batch, share a single lookup of the source entries.
+    pub fn are_calls_allowed<'a, I>(&self, calls: I) -> Vec<Evaluation>
+    where I: IntoIterator<Item = Call<'a>>
+    {
+        let mut last_source: Option<(&str, Vec<(usize, usize)>)> = None;
+        calls.into_iter()
+            .map(|call| {
+                let from = call.from.get_name();
+                match &last_source {
+                    Some((name, _)) if *name == from => (),
+                    _ => last_source = Some((from, self.source_index.lookup(from))),
                 }
+                let (_, entries) = last_source.as_ref().unwrap();
+                self.evaluate(entries, &call)
+            })
+            .collect()
+    }
+
+    /// Evaluates a call given the positions of the source entries and their
+    /// members matching its source name, in the order of precedence
+    fn evaluate(&self, entries: &[(usize, usize)], call: &Call) -> Evaluation {
+        let Call { from, to, explain, .. } = *call;
+        let mut trace = Vec::new();
+        if explain {
+            trace.push(format!("looking up source '{}'", from.get_name()));
+        }
+        if entries.is_empty() {
+            if explain {
+                trace.push(format!("no rules entry for source '{}'", from.get_name()));
             }
-            None => Decision::SOURCE_UNKNOWN,
+            return Evaluation {
+                decision: Decision::SOURCE_UNKNOWN,
+                reason: format!("source '{}' is not listed in the rules", from.get_name()),
+                matched_rule: None,
+                trace,
+            };
         }
+
+        let mut source_matched = false;
+        let mut source_mismatch = None;
+        let mut destination_mismatch = None;
+        let mut allowing = None;
+        for &(i, m) in entries {
+            let entry = &self.sources[i];
+            let member = &entry.members[m];
+            if explain {
+                trace.push(format!(
+                    "found source entry {} with {} destinations by {}",
+                    entry.rule, entry.destinations.len(), member));
+            }
+            if let Some(mismatch) = member.mismatch(from) {
+                if explain {
+                    trace.push(format!("source {} of {}", mismatch, entry.rule));
+                }
+                source_mismatch.get_or_insert((entry, mismatch));
+                continue;
+            }
+            source_matched = true;
+            let destinations = entry.destination_index.lookup(to.get_name());
+            for &(j, n) in &destinations {
+                let destination = &entry.destinations[j];
+                let member = &destination.members[n];
+                if let Some(mismatch) = member.mismatch(to) {
+                    if explain {
+                        trace.push(format!("destination {} of {}", mismatch, destination.rule));
+                    }
+                    destination_mismatch.get_or_insert((destination, mismatch));
+                    continue;
+                }
+                if explain {
+                    trace.push(format!(
+                        "destination '{}' matched {}{} by {}",
+                        to.get_name(), if destination.deny { "deny rule " } else { "" },
+                        destination.rule, member));
+                }
+                if destination.deny {
+                    return Evaluation {
+                        decision: Decision::DISALLOWED_DESTINATION,
+                        reason: format!(
+                            "'{}' may not call '{}', denied by rule {}",
+                            from.get_name(), to.get_name(), destination.rule),
+                        matched_rule: None,
+                        trace,
+                    };
+                }
+                allowing.get_or_insert(destination);
+            }
+            if explain && destinations.is_empty() {
+                trace.push(format!(
+                    "destination '{}' is not listed in {}", to.get_name(), entry.rule));
+            }
+        }
+
+        if let Some(destination) = allowing {
+            return evaluate_destination(destination, &self.schemas, call, trace);
+        }
+        let (decision, reason) = match (source_mismatch, destination_mismatch) {
+            (Some((entry, mismatch)), _) if !source_matched => (
+                Decision::SOURCE_UNKNOWN,
+                format!(
+                    "source '{}' is not listed in the rules, source {} of {}",
+                    from.get_name(), mismatch, entry.rule)),
+            (_, Some((destination, mismatch))) => (
+                Decision::DISALLOWED_DESTINATION,
+                format!(
+                    "no rule allows '{}' to call '{}', destination {} of {}",
+                    from.get_name(), to.get_name(), mismatch, destination.rule)),
+            _ => (
+                Decision::DISALLOWED_DESTINATION,
+                format!("no rule allows '{}' to call '{}'", from.get_name(), to.get_name())),
+        };
+        Evaluation { decision, reason, matched_rule: None, trace }
+    }
+}
+
+type JsonObject = HashMap<String, tinyjson::JsonValue>;
+
+/// Appends a key to a JSON path
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Describes a source object by its name, regex or group
+fn describe_source(source: &tinyjson::JsonValue) -> String {
+    let object = source.get::<JsonObject>();
+    let field = |key| object.and_then(|object| object.get(key)?.get::<String>());
+    match (field("name"), field("name_regex"), field("group")) {
+        (Some(name), _, _) => format!("name '{}'", name),
+        (_, Some(regex), _) => format!("regex '{}'", regex),
+        (_, _, Some(group)) => format!("group '{}'", group),
+        _ => "without a name".to_owned(),
+    }
+}
+
+/// Builds the entries of rules files, reporting the first invalid value
+struct Parser<'a> {
+    /// File being parsed
+    file: &'a Path,
+    /// Prefix of the ids of the rules of the file
+    rule_prefix: String,
+    schemas: &'a SchemaRegistry,
+    groups: HashMap<String, Vec<Member>>,
+    /// Files the groups are defined in
+    group_files: HashMap<String, &'a Path>,
+    duplicates: Option<DuplicatePolicy>,
+    match_pid: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, path: &str, reason: impl Into<String>) -> RulesError {
+        RulesError { file: self.file.to_owned(), path: path.to_owned(), reason: reason.into() }
+    }
+
+    fn object<'v>(
+        &self, value: &'v tinyjson::JsonValue, path: &str
+    ) -> Result<&'v JsonObject, RulesError> {
+        value.get().ok_or_else(|| self.error(path, "should be an object"))
+    }
+
+    fn array<'v>(
+        &self, value: &'v tinyjson::JsonValue, path: &str
+    ) -> Result<&'v Vec<tinyjson::JsonValue>, RulesError> {
+        value.get().ok_or_else(|| self.error(path, "should be an array"))
+    }
+
+    fn string<'v>(
+        &self, value: &'v tinyjson::JsonValue, path: &str
+    ) -> Result<&'v String, RulesError> {
+        value.get().ok_or_else(|| self.error(path, "should be a string"))
+    }
+
+    /// Parses the groups of all the files first, so that any file may use
+    /// them, and then the entries of the files in their order
+    ///
+    /// A file is either a bare array of entries or an object with `rules`,
+    /// `groups` and `include`, any of which may be left out.
+    fn rules(
+        &mut self, documents: &'a [Document], root: &Path
+    ) -> Result<(Vec<SourceEntry>, Vec<Duplicate>), RulesError> {
+        let several = documents.len() > 1;
+        let name = |file: &Path| file.strip_prefix(root).unwrap_or(file).display().to_string();
+        for document in documents {
+            self.file = &document.file;
+            self.groups(document).map_err(|e| document.locate(e))?;
+        }
+
+        let mut sources = Vec::new();
+        let mut duplicates = Vec::new();
+        // Sources seen so far with their documents, the descriptions of their
+        // entries and the positions of the entries in `sources`, by name
+        let mut seen = HashMap::<String, Vec<(&tinyjson::JsonValue, usize, String, usize)>>::new();
+        for (d, document) in documents.iter().enumerate() {
+            self.file = &document.file;
+            // Paths and rules are named by their files when there are several
+            let qualify = |path: &str| match several {
+                true => format!("{}: {}", name(&document.file), path),
+                false => path.to_owned(),
+            };
+            self.rule_prefix = qualify("");
+            let (entries, path) = self.entries(&document.value)
+                .map_err(|e| document.locate(e))?;
+            for (i, entry) in entries.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                let source = self.object(entry, &path)
+                    .and_then(|object| object.get("source")
+                        .ok_or_else(|| self.error(&path, "missing 'source'")))
+                    .map_err(|e| document.locate(e))?;
+                let name = ["name", "name_regex", "group"].iter()
+                    .filter_map(|key| source.get::<JsonObject>()?.get(*key)?.get::<String>())
+                    .fold(String::new(), |name, part| name + part);
+                let source_entry = self.source_entry(entry, &path, i)
+                    .map_err(|e| document.locate(e))?;
+                let same_name = seen.entry(name).or_default();
+                let first = match same_name.iter_mut().find(|(seen, ..)| *seen == source) {
+                    Some(first) => first,
+                    None => {
+                        let entry = qualify(&document.position(&path));
+                        same_name.push((source, d, entry, sources.len()));
+                        sources.push(source_entry);
+                        continue;
+                    },
+                };
+                let (_, first_document, first_path, position) = first;
+                let same_document = *first_document == d;
+                let policy = match self.duplicates {
+                    // Statements of a policy share their sources by design
+                    _ if same_document && document.format == RulesFormat::Policy => {
+                        DuplicatePolicy::Merge
+                    },
+                    Some(policy) => policy,
+                    None if same_document => DuplicatePolicy::LastWins,
+                    None => DuplicatePolicy::Merge,
+                };
+                let resolution = match policy {
+                    DuplicatePolicy::Error => return Err(document.locate(self.error(
+                        &join(&path, "source"),
+                        format!("duplicate of the source of {}", first_path)))),
+                    DuplicatePolicy::Merge => {
+                        let merged = &mut sources[*position];
+                        merged.destinations.extend(source_entry.destinations);
+                        merged.destination_index = MemberIndex::new(
+                            merged.destinations.iter().map(|d| d.members.as_slice()));
+                        Resolution::Merged
+                    },
+                    DuplicatePolicy::LastWins => {
+                        // The entry keeps its place among the others
+                        sources[*position] = source_entry;
+                        Resolution::Overwritten
+                    },
+                };
+                let duplicate = Duplicate {
+                    source: describe_source(source),
+                    path: qualify(&document.position(&path)),
+                    first: first_path.clone(),
+                    resolution,
+                };
+                if resolution == Resolution::Overwritten {
+                    // Later duplicates overwrite the entry which won
+                    *first_document = d;
+                    *first_path = duplicate.path.clone();
+                }
+                duplicates.push(duplicate);
+            }
+        }
+        Ok((sources, duplicates))
+    }
+
+    /// Adds the groups of the file, which may not be defined by other files
+    fn groups(&mut self, document: &'a Document) -> Result<(), RulesError> {
+        let groups = match document.value.get::<JsonObject>().and_then(|o| o.get("groups")) {
+            Some(groups) => groups,
+            None => return Ok(()),
+        };
+        let mut groups: Vec<_> = self.parse_groups(groups, "groups")?.into_iter().collect();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (group, members) in groups {
+            if let Some(file) = self.group_files.get(&group) {
+                return Err(self.error(
+                    &join("groups", &group),
+                    format!("group '{}' is already defined in {}", group, file.display())));
+            }
+            self.group_files.insert(group.clone(), &document.file);
+            self.groups.insert(group, members);
+        }
+        Ok(())
+    }
+
+    /// Finds the entries of the file with their JSON path
+    fn entries<'v>(
+        &self, value: &'v tinyjson::JsonValue
+    ) -> Result<(&'v [tinyjson::JsonValue], &'static str), RulesError> {
+        match value.get::<JsonObject>() {
+            Some(object) => match object.get("rules") {
+                Some(rules) => Ok((self.array(rules, "rules")?, "rules")),
+                None if object.contains_key("groups") || object.contains_key("include") => {
+                    Ok((&[], "rules"))
+                },
+                None => Err(self.error("", "missing 'rules'")),
+            },
+            None => match value.get::<Vec<_>>() {
+                Some(entries) => Ok((entries, "")),
+                None => Err(self.error("", "should be an array or an object")),
+            },
+        }
+    }
+
+    fn source_entry(
+        &self, value: &tinyjson::JsonValue, path: &str, i: usize
+    ) -> Result<SourceEntry, RulesError> {
+        let object = self.object(value, path)?;
+        let rule = format!("{}rules[{}]", self.rule_prefix, i);
+        let members = self.members(&object["source"], &join(path, "source"))?;
+        let destinations_path = join(path, "destinations");
+        let destinations = object.get("destinations")
+            .ok_or_else(|| self.error(path, "missing 'destinations'"))?;
+        let destinations = self.array(destinations, &destinations_path)?
+            .iter()
+            .enumerate()
+            .map(|(j, destination)| self.destination(
+                destination, &format!("{}[{}]", destinations_path, j),
+                format!("{}.destinations[{}]", rule, j)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SourceEntry {
+            rule,
+            members,
+            destination_index: MemberIndex::new(destinations.iter().map(|d| d.members.as_slice())),
+            destinations,
+        })
+    }
+
+    fn destination(
+        &self, value: &tinyjson::JsonValue, path: &str, default_rule: String
+    ) -> Result<DestinationEntry, RulesError> {
+        let object = self.object(value, path)?;
+        let rule = match object.get("id") {
+            Some(id) => format!("{}{}", self.rule_prefix, self.string(id, &join(path, "id"))?),
+            None => default_rule,
+        };
+        let members = self.members(value, path)?;
+        let message_rules = match object.get("message_rules") {
+            Some(value) => {
+                let path = join(path, "message_rules");
+                self.array(value, &path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(k, value)| MessageRule::from_json(value, self.schemas)
+                        .map_err(|e| self.error(&format!("{}[{}]", path, k), e)))
+                    .collect::<Result<Vec<_>, _>>()?
+            },
+            None => Vec::new(),
+        };
+        let allowed_types = match object.get("allowed_types") {
+            Some(types) => {
+                let path = join(path, "allowed_types");
+                Some(self.array(types, &path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(k, type_url)| match type_url.get::<String>() {
+                        Some(type_url) if !type_url.is_empty() => Ok(type_url.clone()),
+                        _ => Err(self.error(
+                            &format!("{}[{}]", path, k), "should be a non-empty string")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?)
+            },
+            None => None,
+        };
+        let deny = match object.get("effect") {
+            None => false,
+            Some(effect) => match effect.get::<String>().map(String::as_str) {
+                Some("allow") => false,
+                Some("deny") => true,
+                _ => return Err(self.error(
+                    &join(path, "effect"), "should be \"allow\" or \"deny\"")),
+            },
+        };
+        if deny && (allowed_types.is_some() || !message_rules.is_empty()) {
+            return Err(self.error(path, "deny rules cannot have allowed types or message rules"));
+        }
+        Ok(DestinationEntry { rule, members, deny, allowed_types, message_rules })
+    }
+
+    /// Parses the `groups` object, which maps group names to arrays of
+    /// members, each member being a name or an object such as parsed by
+    /// `member`
+    fn parse_groups(
+        &self, value: &tinyjson::JsonValue, path: &str
+    ) -> Result<HashMap<String, Vec<Member>>, RulesError> {
+        self.object(value, path)?
+            .iter()
+            .map(|(group, members)| {
+                let path = join(path, group);
+                let members = self.array(members, &path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(k, member)| {
+                        let path = format!("{}[{}]", path, k);
+                        match member.get::<String>() {
+                            Some(name) => Ok(Member {
+                                pattern: NamePattern::from_name(name)
+                                    .map_err(|e| self.error(&path, e))?,
+                                pid: PidMatch::Any,
+                                labels: None,
+                                group: Some(group.clone()),
+                            }),
+                            None => {
+                                if self.object(member, &path)?.contains_key("group") {
+                                    return Err(self.error(&path, "groups cannot be nested"));
+                                }
+                                self.member(member, &path, Some(group.clone()))
+                            },
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((group.clone(), members))
+            })
+            .collect()
+    }
+
+    /// Parses a source or destination object, which either is parsed by
+    /// `member` or references a group by `group`
+    fn members(&self, value: &tinyjson::JsonValue, path: &str) -> Result<Vec<Member>, RulesError> {
+        let object = self.object(value, path)?;
+        match object.get("group") {
+            Some(group) => {
+                let group = self.string(group, &join(path, "group"))?;
+                let keys = ["name", "name_regex", "pid", "labels"];
+                if keys.iter().any(|key| object.contains_key(*key)) {
+                    return Err(self.error(
+                        path, "cannot have a name, pid or labels together with a group"));
+                }
+                self.groups.get(group)
+                    .cloned()
+                    .ok_or_else(|| self.error(
+                        &join(path, "group"), format!("unknown group '{}'", group)))
+            },
+            None => Ok(vec![self.member(value, path, None)?]),
+        }
+    }
+
+    /// Parses an object with a name pattern given by `name` or `name_regex`,
+    /// optional `pid` and optional `labels` selector, the name may be left
+    /// out when labels are given
+    fn member(
+        &self, value: &tinyjson::JsonValue, path: &str, group: Option<String>
+    ) -> Result<Member, RulesError> {
+        let object = self.object(value, path)?;
+        let labels = match object.get("labels") {
+            Some(labels) => {
+                let path = join(path, "labels");
+                Some(LabelSelector::parse(self.string(labels, &path)?)
+                    .map_err(|e| self.error(&path, e))?)
+            },
+            None => None,
+        };
+        let pattern = match labels {
+            Some(_) if !object.contains_key("name") && !object.contains_key("name_regex") => {
+                NamePattern::Any
+            },
+            _ => NamePattern::from_json(value).map_err(|e| self.error(path, e))?,
+        };
+        let pid = match object.get("pid") {
+            Some(pid) => self.pid(pid, &join(path, "pid"))?,
+            None => PidMatch::Any,
+        };
+        // Unless asked to match them, pids only document the instances as
+        // they always have
+        let pid = if self.match_pid { pid } else { PidMatch::Any };
+        Ok(Member { pattern, pid, labels, group })
+    }
+
+    /// Parses `pid` of a source or destination, which is a single id, an
+    /// array of ids or `"any"`
+    fn pid(&self, value: &tinyjson::JsonValue, path: &str) -> Result<PidMatch, RulesError> {
+        let invalid = || self.error(
+            path, "should be a non-negative integer, an array of them or \"any\"");
+        let parse_id = |value: &tinyjson::JsonValue| match value.get::<f64>() {
+            Some(id) if *id >= 0.0 && id.fract() == 0.0 && *id <= MAX_SAFE_ID => Ok(*id as u64),
+            _ => Err(invalid()),
+        };
+        match value.get::<String>() {
+            Some(any) if any == "any" => Ok(PidMatch::Any),
+            Some(_) => Err(invalid()),
+            None => match value.get::<Vec<_>>() {
+                Some(ids) => Ok(PidMatch::OneOf(
+                    ids.iter().map(parse_id).collect::<Result<_, _>>()?)),
+                None => Ok(PidMatch::OneOf(vec![parse_id(value)?])),
+            },
+        }
+    }
+}
+
+/// Evaluates a call against the destination entry which decides it
+fn evaluate_destination(
+    destination: &DestinationEntry, schemas: &SchemaRegistry, call: &Call,
+    mut trace: Vec<String>
+) -> Evaluation {
+    let Call { from, to, payload, type_url, explain } = *call;
+    let rule = &destination.rule;
+    if let Some(allowed_types) = &destination.allowed_types {
+        let reason = match type_url {
+            Some(type_url) if allowed_types.iter()
+                .any(|allowed| type_matches(allowed, type_url)) => None,
+            Some(type_url) => Some(format!(
+                "payload type '{}' from '{}' to '{}' is not allowed by rule {}",
+                type_url, from.get_name(), to.get_name(), rule)),
+            None => Some(format!(
+                "rule {} allows '{}' to call '{}' only with typed payloads",
+                rule, from.get_name(), to.get_name())),
+        };
+        if explain {
+            trace.push(format!(
+                "payload type {} {} allowed types of {}",
+                type_url.map_or("(none)".to_owned(), |url| format!("'{}'", url)),
+                if reason.is_none() { "is among" } else { "is not among" },
+                rule));
+        }
+        if let Some(reason) = reason {
+            return Evaluation {
+                decision: Decision::DISALLOWED_MESSAGE,
+                reason,
+                matched_rule: None,
+                trace,
+            };
+        }
+    }
+    for (k, message_rule) in destination.message_rules.iter().enumerate() {
+        let result = match (message_rule, type_url) {
+            // A typed payload names its type, which has to be the one expected
+            (MessageRule::Typed { message, .. }, Some(type_url))
+                    if !type_matches(message, type_url) => Err(Violation::Disallowed(format!(
+                "payload type '{}' is not {}", type_url, message))),
+            _ => message_rule.check(payload, schemas),
+        };
+        if explain {
+            trace.push(format!(
+                "message rule {}.message_rules[{}] ({}) {}",
+                rule, k, message_rule,
+                if result.is_ok() { "holds" } else { "fails" }));
+        }
+        let violation = match result {
+            Ok(()) => continue,
+            Err(violation) => violation,
+        };
+        let decision = match violation {
+            Violation::Malformed(_) => Decision::MALFORMED_MESSAGE,
+            Violation::Disallowed(_) => Decision::DISALLOWED_MESSAGE,
+        };
+        return Evaluation {
+            decision,
+            reason: format!(
+                "message from '{}' to '{}' violates {}.message_rules[{}]: {}",
+                from.get_name(), to.get_name(), rule, k, violation),
+            matched_rule: None,
+            trace,
+        };
+    }
+    Evaluation {
+        decision: Decision::ALLOW,
+        reason: format!(
+            "'{}' may call '{}' by rule {}", from.get_name(), to.get_name(), rule),
+        matched_rule: Some(rule.clone()),
+        trace,
+    }
+}
+
+/// Matches a type URL against an entry of `allowed_types`, which is either
+/// a full type URL or just a fully-qualified message name
+fn type_matches(allowed: &str, type_url: &str) -> bool {
+    if allowed.contains('/') {
+        allowed == type_url
+    } else {
+        type_url.rsplit('/').next() == Some(allowed)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::prelude::*;
+    use std::sync::Arc;
     use osmose_identifier::Identifier;
-    use crate::RulesDatabase;
+    use crate::rules_database::{Call, DuplicatePolicy, LoadOptions, RulesDatabase};
+    use crate::rules_format::RulesFormat;
+    use crate::schema::{test_refund, test_registry};
     use osmose_generated::generated_proto::osmose::Decision as Decision;
 
+    fn call<'a>(
+        from: &'a Identifier, to: &'a Identifier, payload: &'a [u8], explain: bool
+    ) -> Call<'a> {
+        Call { from, to, payload, type_url: None, explain }
+    }
+
     fn get_test_config() -> String {
         r#"
 [
@@ -94,14 +1005,99 @@ mod tests {
                 "name": "process1"
             }
         ]
+    },
+    {
+        "source": {
+            "name": "gateway"
+        },
+        "destinations": [
+            {
+                "name": "process1",
+                "message_rules": [
+                    { "max_size": 16 },
+                    { "prefix_hex": "0a" },
+                    { "field": 1, "equals": "Get" }
+                ]
+            },
+            {
+                "name": "process2",
+                "allowed_types": [
+                    "type.googleapis.com/payments.Refund",
+                    "payments.Query"
+                ]
+            }
+        ]
+    },
+    {
+        "source": {
+            "name": "pinned",
+            "pid": [111, 112]
+        },
+        "destinations": [
+            {
+                "name": "process1",
+                "pid": 222
+            },
+            {
+                "name": "process2",
+                "pid": "any"
+            }
+        ]
+    },
+    {
+        "source": {
+            "name": "payments-*"
+        },
+        "destinations": [
+            {
+                "name": "*.internal"
+            },
+            {
+                "name": "secrets.internal",
+                "effect": "deny"
+            }
+        ]
+    },
+    {
+        "source": {
+            "name": "payments-api"
+        },
+        "destinations": [
+            {
+                "name": "db.internal",
+                "message_rules": [{ "max_size": 4 }]
+            }
+        ]
+    },
+    {
+        "source": {
+            "name_regex": "batch-[0-9]+"
+        },
+        "destinations": [
+            {
+                "name_regex": "ledger|audit"
+            }
+        ]
     }
 ]
             "#.to_owned()
     }
 
-    fn set_up(config_name: &str) -> std::io::Result<()> {
+    fn load(config_name: &str) -> RulesDatabase {
+        RulesDatabase::load(
+            std::path::Path::new(config_name), Default::default(), Default::default())
+            .unwrap()
+    }
+
+    fn load_matching_pid(config_name: &str) -> RulesDatabase {
+        let options = LoadOptions { match_pid: true, ..Default::default() };
+        RulesDatabase::load(std::path::Path::new(config_name), Default::default(), options)
+            .unwrap()
+    }
+
+    fn set_up(config_name: &str, config: &str) -> std::io::Result<()> {
         let mut file = std::fs::File::create(config_name)?;
-        file.write_all(get_test_config().as_bytes())?;
+        file.write_all(config.as_bytes())?;
         Ok(())
     }
 
@@ -110,10 +1106,16 @@ mod tests {
         Ok(())
     }
 
-    fn run_test<T>(test: T, config_name: &str) -> ()
-    where T: FnOnce() -> () + std::panic::UnwindSafe
+    fn run_test<T>(test: T, config_name: &str)
+    where T: FnOnce() + std::panic::UnwindSafe
+    {
+        run_test_with_config(test, config_name, &get_test_config())
+    }
+
+    fn run_test_with_config<T>(test: T, config_name: &str, config: &str)
+    where T: FnOnce() + std::panic::UnwindSafe
     {
-        set_up(config_name)
+        set_up(config_name, config)
             .expect("Cannot create test configuration file");
         let result = std::panic::catch_unwind(|| {
             test()
@@ -127,7 +1129,7 @@ mod tests {
     fn test_create_db() {
         let config_name = "test_create_db_cfg.json";
         run_test(|| {
-            let _ = RulesDatabase::new(std::path::Path::new(config_name));
+            let _ = load(config_name);
         }, config_name);
     }
 
@@ -138,10 +1140,10 @@ mod tests {
             let id1 = Identifier::from_given("process1", 111);
             let id2 = Identifier::from_given("process2", 222);
             let id3 = Identifier::from_given("process3", 333);
-            let db = RulesDatabase::new(std::path::Path::new(config_name));
-            assert_eq!(db.is_call_allowed(&id1, &id2), Decision::ALLOW);
-            assert_eq!(db.is_call_allowed(&id1, &id3), Decision::ALLOW);
-            assert_eq!(db.is_call_allowed(&id2, &id1), Decision::ALLOW);
+            let db = load(config_name);
+            assert_eq!(db.is_call_allowed(&call(&id1, &id2, b"", false)).decision, Decision::ALLOW);
+            assert_eq!(db.is_call_allowed(&call(&id1, &id3, b"", false)).decision, Decision::ALLOW);
+            assert_eq!(db.is_call_allowed(&call(&id2, &id1, b"", false)).decision, Decision::ALLOW);
         }, config_name);
     }
 
@@ -153,22 +1155,592 @@ mod tests {
             let id2 = Identifier::from_given("process2", 222);
             let id3 = Identifier::from_given("process3", 333);
             let id4 = Identifier::from_given("process4", 444);
-            let db = RulesDatabase::new(std::path::Path::new(config_name));
+            let db = load(config_name);
             assert_eq!(
-                db.is_call_allowed(&id1, &id4),
+                db.is_call_allowed(&call(&id1, &id4, b"", false)).decision,
                 Decision::DISALLOWED_DESTINATION);
             assert_eq!(
-                db.is_call_allowed(&id2, &id3),
+                db.is_call_allowed(&call(&id2, &id3, b"", false)).decision,
                 Decision::DISALLOWED_DESTINATION);
             assert_eq!(
-                db.is_call_allowed(&id3, &id1),
+                db.is_call_allowed(&call(&id3, &id1, b"", false)).decision,
+                Decision::SOURCE_UNKNOWN);
+            assert_eq!(
+                db.is_call_allowed(&call(&id4, &id1, b"", false)).decision,
                 Decision::SOURCE_UNKNOWN);
             assert_eq!(
-                db.is_call_allowed(&id4, &id1),
+                db.is_call_allowed(&call(&id4, &id3, b"", false)).decision,
                 Decision::SOURCE_UNKNOWN);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_explain() {
+        let config_name = "test_explain_cfg.json";
+        run_test(|| {
+            let id1 = Identifier::from_given("process1", 111);
+            let id3 = Identifier::from_given("process3", 333);
+            let id4 = Identifier::from_given("process4", 444);
+            let db = load(config_name);
+
+            let evaluation = db.is_call_allowed(&call(&id1, &id3, b"", true));
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert_eq!(
+                evaluation.matched_rule.as_deref(), Some("rules[0].destinations[1]"));
+            assert_eq!(evaluation.trace.len(), 3);
+
+            let evaluation = db.is_call_allowed(&call(&id1, &id4, b"", false));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_DESTINATION);
+            assert_eq!(evaluation.matched_rule, None);
+            assert!(evaluation.reason.contains("process4"));
+            assert!(evaluation.trace.is_empty());
+
+            let evaluation = db.is_call_allowed(&call(&id4, &id1, b"", true));
+            assert_eq!(evaluation.decision, Decision::SOURCE_UNKNOWN);
+            assert!(!evaluation.trace.is_empty());
+        }, config_name);
+    }
+
+    #[test]
+    fn test_message_rules() {
+        let config_name = "test_message_rules_cfg.json";
+        run_test(|| {
+            let gateway = Identifier::from_given("gateway", 111);
+            let id1 = Identifier::from_given("process1", 222);
+            let db = load(config_name);
+
+            let evaluation = db.is_call_allowed(&call(&gateway, &id1, b"\x0a\x03Get", true));
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert_eq!(evaluation.trace.len(), 6);
+
+            let evaluation = db.is_call_allowed(&call(&gateway, &id1, b"\x0a\x06Delete", false));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_MESSAGE);
+            assert!(evaluation.reason.contains("rules[2].destinations[0].message_rules[2]"));
+
+            assert_eq!(
+                db.is_call_allowed(&call(&gateway, &id1, &[0x0a; 17], false)).decision,
+                Decision::DISALLOWED_MESSAGE);
+            assert_eq!(
+                db.is_call_allowed(&call(&gateway, &id1, b"Get", false)).decision,
+                Decision::DISALLOWED_MESSAGE);
+            assert_eq!(
+                db.is_call_allowed(&call(&gateway, &id1, b"\x0a\x09Get", false)).decision,
+                Decision::MALFORMED_MESSAGE);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_allowed_types() {
+        let config_name = "test_allowed_types_cfg.json";
+        run_test(|| {
+            let gateway = Identifier::from_given("gateway", 111);
+            let id2 = Identifier::from_given("process2", 222);
+            let db = load(config_name);
+            let typed = |type_url| Call {
+                type_url: Some(type_url), ..call(&gateway, &id2, b"", true)
+            };
+
+            let evaluation = db.is_call_allowed(&typed("type.googleapis.com/payments.Refund"));
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert_eq!(evaluation.trace.len(), 4);
+            assert_eq!(
+                db.is_call_allowed(&typed("example.com/types/payments.Query")).decision,
+                Decision::ALLOW);
+
+            let evaluation = db.is_call_allowed(&typed("type.googleapis.com/payments.Charge"));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_MESSAGE);
+            assert!(evaluation.reason.contains("payments.Charge"));
             assert_eq!(
-                db.is_call_allowed(&id4, &id3),
+                db.is_call_allowed(&typed("example.com/payments.Refund")).decision,
+                Decision::DISALLOWED_MESSAGE);
+            assert_eq!(
+                db.is_call_allowed(&call(&gateway, &id2, b"", false)).decision,
+                Decision::DISALLOWED_MESSAGE);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_typed_message_rules() {
+        let config_name = "test_typed_message_rules_cfg.json";
+        let config = r#"[{
+            "source": {"name": "gateway"},
+            "destinations": [
+                {"name": "process2", "message_rules": [{"message": "payments.Refund"}]}
+            ]
+        }]"#;
+        run_test_with_config(|| {
+            let gateway = Identifier::from_given("gateway", 111);
+            let id2 = Identifier::from_given("process2", 222);
+            let refund = test_refund();
+            let db = RulesDatabase::load(
+                std::path::Path::new(config_name), Arc::new(test_registry()), Default::default())
+                .unwrap();
+            let typed = |type_url| Call { type_url, ..call(&gateway, &id2, &refund, false) };
+
+            assert_eq!(db.is_call_allowed(&typed(None)).decision, Decision::ALLOW);
+            assert_eq!(
+                db.is_call_allowed(&typed(Some("type.googleapis.com/payments.Refund"))).decision,
+                Decision::ALLOW);
+            // The payload decodes as a refund, but says it is something else
+            let evaluation =
+                db.is_call_allowed(&typed(Some("type.googleapis.com/payments.Header")));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_MESSAGE);
+            assert!(evaluation.reason.contains("payments.Header"), "{}", evaluation.reason);
+        }, config_name, config);
+    }
+
+    #[test]
+    fn test_pid() {
+        let config_name = "test_pid_cfg.json";
+        run_test(|| {
+            let pinned = Identifier::from_given("pinned", 112);
+            let decision = |db: &RulesDatabase, from: &Identifier, to: &Identifier| {
+                db.is_call_allowed(&call(from, to, b"", false)).decision
+            };
+
+            // Pids are not checked unless asked to
+            let db = load(config_name);
+            assert_eq!(
+                decision(&db, &pinned, &Identifier::from_given("process1", 223)), Decision::ALLOW);
+
+            let db = load_matching_pid(config_name);
+            let decision = |from: &Identifier, to: &Identifier| decision(&db, from, to);
+
+            assert_eq!(
+                decision(&pinned, &Identifier::from_given("process1", 222)), Decision::ALLOW);
+            assert_eq!(
+                decision(&pinned, &Identifier::from_given("process2", 333)), Decision::ALLOW);
+            assert_eq!(
+                decision(&pinned, &Identifier::from_given("process1", 223)),
+                Decision::DISALLOWED_DESTINATION);
+            assert_eq!(
+                decision(
+                    &Identifier::from_given("pinned", 113),
+                    &Identifier::from_given("process1", 222)),
                 Decision::SOURCE_UNKNOWN);
+
+            let evaluation = db.is_call_allowed(&call(
+                &pinned, &Identifier::from_given("process1", 223), b"", true));
+            assert!(evaluation.reason.contains("pid 222"), "{}", evaluation.reason);
+            assert_eq!(evaluation.trace.len(), 3);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_name_patterns() {
+        let config_name = "test_name_patterns_cfg.json";
+        run_test(|| {
+            let db = load(config_name);
+            let evaluate = |from: &str, to: &str, payload: &[u8]| db.is_call_allowed(&call(
+                &Identifier::from_given(from, 1), &Identifier::from_given(to, 2), payload, true));
+
+            let evaluation = evaluate("payments-refunds", "cache.eu.internal", b"");
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert_eq!(evaluation.matched_rule.as_deref(), Some("rules[4].destinations[0]"));
+            assert!(evaluation.trace[1].contains("glob 'payments-*'"), "{:?}", evaluation.trace);
+
+            // The exact source entry takes precedence over the glob
+            let evaluation = evaluate("payments-api", "db.internal", b"12345");
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_MESSAGE);
+            assert!(evaluation.reason.contains("rules[5].destinations[0]"));
+            assert_eq!(
+                evaluate("payments-api", "db.internal", b"1234").matched_rule.as_deref(),
+                Some("rules[5].destinations[0]"));
+            assert_eq!(
+                evaluate("payments-api", "cache.internal", b"12345").matched_rule.as_deref(),
+                Some("rules[4].destinations[0]"));
+
+            assert_eq!(evaluate("batch-42", "ledger", b"").decision, Decision::ALLOW);
+            assert_eq!(
+                evaluate("batch-42", "ledger-2", b"").decision,
+                Decision::DISALLOWED_DESTINATION);
+            assert_eq!(evaluate("batch-x", "ledger", b"").decision, Decision::SOURCE_UNKNOWN);
+            assert_eq!(
+                evaluate("payments-api", "internal", b"").decision,
+                Decision::DISALLOWED_DESTINATION);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_deny() {
+        let config_name = "test_deny_cfg.json";
+        run_test(|| {
+            let db = load(config_name);
+            let api = Identifier::from_given("payments-api", 1);
+            let secrets = Identifier::from_given("secrets.internal", 2);
+
+            let evaluation = db.is_call_allowed(&call(&api, &secrets, b"", true));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_DESTINATION);
+            assert_eq!(evaluation.matched_rule, None);
+            assert!(
+                evaluation.reason.contains("denied by rule rules[4].destinations[1]"),
+                "{}", evaluation.reason);
+            assert!(evaluation.trace.last().unwrap().contains("deny rule"));
+
+            assert_eq!(
+                db.is_call_allowed(
+                    &call(&api, &Identifier::from_given("db.internal", 2), b"", false))
+                    .decision,
+                Decision::ALLOW);
+        }, config_name);
+    }
+
+    #[test]
+    fn test_groups() {
+        let config_name = "test_groups_cfg.json";
+        let config = r#"
+{
+    "groups": {
+        "frontend": ["web-1", { "name": "mobile-*", "pid": [7, 8] }],
+        "billing": [{ "name_regex": "invoices|ledger" }]
+    },
+    "rules": [
+        {
+            "source": { "group": "frontend" },
+            "destinations": [
+                { "group": "billing" },
+                { "name": "search" }
+            ]
+        },
+        {
+            "source": { "name": "ledger" },
+            "destinations": [{ "group": "frontend" }]
+        }
+    ]
+}
+            "#;
+        run_test_with_config(|| {
+            let db = load_matching_pid(config_name);
+            let evaluate = |from: (&str, u64), to: (&str, u64)| db.is_call_allowed(&call(
+                &Identifier::from_given(from.0, from.1), &Identifier::from_given(to.0, to.1),
+                b"", true));
+
+            let evaluation = evaluate(("mobile-ios", 7), ("ledger", 1));
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert_eq!(evaluation.matched_rule.as_deref(), Some("rules[0].destinations[0]"));
+            assert!(
+                evaluation.trace[1].contains("glob 'mobile-*' of group 'frontend'"),
+                "{:?}", evaluation.trace);
+            assert!(evaluation.trace[2].contains("of group 'billing'"), "{:?}", evaluation.trace);
+
+            assert_eq!(evaluate(("web-1", 1), ("search", 1)).decision, Decision::ALLOW);
+            assert_eq!(evaluate(("ledger", 1), ("web-1", 1)).decision, Decision::ALLOW);
+            assert_eq!(
+                evaluate(("mobile-ios", 9), ("ledger", 1)).decision, Decision::SOURCE_UNKNOWN);
+            assert_eq!(
+                evaluate(("ledger", 1), ("search", 1)).decision,
+                Decision::DISALLOWED_DESTINATION);
+            assert_eq!(evaluate(("search", 1), ("web-1", 1)).decision, Decision::SOURCE_UNKNOWN);
+        }, config_name, config);
+    }
+
+    #[test]
+    fn test_labels() {
+        let config_name = "test_labels_cfg.json";
+        let config = r#"
+[
+    {
+        "source": { "labels": "env=prod, team in (payments, billing)" },
+        "destinations": [
+            { "name": "audit", "labels": "env=prod, !canary" }
+        ]
+    }
+]
+            "#;
+        run_test_with_config(|| {
+            let db = load(config_name);
+            let evaluate = |from: &Identifier, to: &Identifier| {
+                db.is_call_allowed(&call(from, to, b"", true))
+            };
+            let refunds = Identifier::from_given("refunds", 1)
+                .with_label("env", "prod")
+                .with_label("team", "payments");
+            let audit = Identifier::from_given("audit", 2).with_label("env", "prod");
+
+            let evaluation = evaluate(&refunds, &audit);
+            assert_eq!(evaluation.decision, Decision::ALLOW);
+            assert!(
+                evaluation.trace[1]
+                    .contains("any name with labels 'env=prod, team in (payments, billing)'"),
+                "{:?}", evaluation.trace);
+
+            let evaluation = evaluate(&refunds.clone().with_label("env", "dev"), &audit);
+            assert_eq!(evaluation.decision, Decision::SOURCE_UNKNOWN);
+            assert!(
+                evaluation.reason.contains("labels {env=dev, team=payments} do not match"),
+                "{}", evaluation.reason);
+
+            let evaluation = evaluate(&refunds, &audit.clone().with_label("canary", "1"));
+            assert_eq!(evaluation.decision, Decision::DISALLOWED_DESTINATION);
+            assert_eq!(
+                evaluate(&refunds, &Identifier::from_given("audit-2", 2).with_label("env", "prod"))
+                    .decision,
+                Decision::DISALLOWED_DESTINATION);
+        }, config_name, config);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let config_name = "test_invalid_rules_cfg.json";
+        let load = |config: &str| {
+            std::fs::write(config_name, config).expect("Cannot create test configuration file");
+            let result = RulesDatabase::load(
+                std::path::Path::new(config_name), Default::default(), Default::default());
+            std::fs::remove_file(config_name).expect("Cannot remove test configuration file");
+            let error = result.expect_err(config);
+            assert!(error.file.ends_with(config_name));
+            (error.path, error.reason)
+        };
+        let cases = [
+            (r#"[{"source": {"pid": 1}, "destinations": []}]"#,
+             "[0].source", "either 'name' or 'name_regex' should be given"),
+            (r#"[{"source": {"name": "a"}, "destinations": {}}]"#,
+             "[0].destinations", "should be an array"),
+            (r#"[{"source": {"name": "a"}}]"#, "[0]", "missing 'destinations'"),
+            (r#"[{"source": {"name": "a"}, "destinations": [{"name": "b", "pid": -1}]}]"#,
+             "[0].destinations[0].pid", "should be a non-negative integer"),
+            (r#"[{"source": {"name": "a"}, "destinations": [{"name": "b", "effect": "drop"}]}]"#,
+             "[0].destinations[0].effect", "should be \"allow\" or \"deny\""),
+            (r#"[{"source": {"name": "a"},
+                  "destinations": [{"name": "b", "message_rules": [{"max_size": "x"}]}]}]"#,
+             "[0].destinations[0].message_rules[0]", "max_size"),
+            (r#"{"rules": [{"source": {"group": "admins"}, "destinations": []}]}"#,
+             "rules[0].source.group", "unknown group 'admins'"),
+            (r#"{"groups": {"admins": [{"group": "root"}]}, "rules": []}"#,
+             "groups.admins[0]", "groups cannot be nested"),
+            (r#"{"groups": []}"#, "groups", "should be an object"),
+            (r#"[{"source": {"name": "a"}, "#, "", "invalid JSON"),
+        ];
+        for (config, path, reason) in cases.iter() {
+            let (error_path, error_reason) = load(config);
+            assert_eq!(&error_path, path, "{}", config);
+            assert!(error_reason.contains(reason), "{}: {}", config, error_reason);
+        }
+
+        let error = RulesDatabase::load(
+            std::path::Path::new("test_missing_cfg.json"), Default::default(), Default::default())
+            .unwrap_err();
+        assert_eq!(error.path, "");
+        assert!(error.to_string().starts_with("test_missing_cfg.json: "), "{}", error);
+    }
+
+    #[test]
+    fn test_rules_formats() {
+        let config_name = "test_rules_formats_cfg.yaml";
+        let config = "
+# Destinations of the gateway
+- source: { name: gateway }
+  destinations:
+    - name: process1
+    - { name: process2, pid: -1 }
+";
+        run_test_with_config(|| {
+            let load = |format| RulesDatabase::load(
+                std::path::Path::new(config_name),
+                Default::default(),
+                LoadOptions { format, ..Default::default() });
+            let error = load(None).unwrap_err();
+            assert_eq!(error.path, "[0].destinations[1].pid");
+            assert!(error.reason.starts_with("should be a non-negative integer"), "{}", error);
+
+            let error = load(Some(RulesFormat::Json)).unwrap_err();
+            assert!(error.reason.starts_with("invalid JSON: "), "{}", error);
+        }, config_name, config);
+
+        let config_name = "test_rules_formats_cfg.toml";
+        let config = r#"
+[[rules]]
+source = { name = "gateway" }
+destinations = [{ name = "process1" }, { name = "process2", pid = 222 }]
+"#;
+        run_test_with_config(|| {
+            let db = load_matching_pid(config_name);
+            let gateway = Identifier::from_given("gateway", 1);
+            let allowed =
+                |to: &Identifier| db.is_call_allowed(&call(&gateway, to, b"", false)).decision;
+            assert_eq!(allowed(&Identifier::from_given("process1", 1)), Decision::ALLOW);
+            assert_eq!(allowed(&Identifier::from_given("process2", 222)), Decision::ALLOW);
+            assert_eq!(
+                allowed(&Identifier::from_given("process2", 1)),
+                Decision::DISALLOWED_DESTINATION);
+        }, config_name, config);
+    }
+
+    #[test]
+    fn test_policy() {
+        let config_name = "test_policy_cfg.policy";
+        let config = r#"
+allow gateway -> process1, process2 when payload.size <= 4
+deny gateway -> process2
+allow process1 -> @backend
+"#;
+        let load = |duplicates| RulesDatabase::load(
+            std::path::Path::new(config_name), Default::default(),
+            LoadOptions { duplicates, ..Default::default() });
+        run_test_with_config(|| {
+            let error = load(Some(DuplicatePolicy::Error)).unwrap_err();
+            assert_eq!(error.path, "line 4 column 19");
+            assert_eq!(error.reason, "unknown group 'backend'");
+        }, config_name, config);
+
+        let config = format!("{}group backend = process2, process3
+", config);
+        run_test_with_config(|| {
+            // Statements repeating a source are merged whatever the duplicate sources policy
+            for duplicates in [None, Some(DuplicatePolicy::Error)] {
+                let db = load(duplicates).unwrap();
+                let report: Vec<_> = db.get_report().duplicates.iter()
+                    .map(|d| d.to_string())
+                    .collect();
+                assert_eq!(report, [
+                    "source name 'gateway' of line 3 column 1 merged into line 2 column 1",
+                ]);
+                let evaluate = |from: &str, to: &str, payload: &[u8]| {
+                    let from = Identifier::from_given(from, 1);
+                    let to = Identifier::from_given(to, 2);
+                    db.is_call_allowed(&call(&from, &to, payload, false))
+                };
+                assert_eq!(evaluate("gateway", "process1", b"1234").decision, Decision::ALLOW);
+                let evaluation = evaluate("gateway", "process1", b"12345");
+                assert_eq!(evaluation.decision, Decision::DISALLOWED_MESSAGE);
+                assert!(evaluation.reason.contains("violates line 2"), "{}", evaluation.reason);
+                let evaluation = evaluate("gateway", "process2", b"");
+                assert_eq!(evaluation.decision, Decision::DISALLOWED_DESTINATION);
+                assert!(
+                    evaluation.reason.contains("denied by rule line 3"), "{}", evaluation.reason);
+                assert_eq!(evaluate("process1", "process3", b"").decision, Decision::ALLOW);
+            }
+        }, config_name, &config);
+    }
+
+    #[test]
+    fn test_rules_directory() {
+        let directory = std::path::Path::new("test_rules_directory");
+        let write = |name: &str, text: &str| {
+            std::fs::write(directory.join(name), text).expect("Cannot write test rules file")
+        };
+        std::fs::create_dir_all(directory).expect("Cannot create test directory");
+        let result = std::panic::catch_unwind(|| {
+            write("payments.policy", "group payments = refunds, ledger
+allow gateway -> @payments
+");
+            write(
+                "search.json",
+                r#"[{"source": {"name": "gateway"}, "destinations": [{"name": "search"}]}]"#);
+            let load = |duplicates| RulesDatabase::load(
+                directory, Default::default(), LoadOptions { duplicates, ..Default::default() });
+
+            let error = load(Some(DuplicatePolicy::Error)).unwrap_err();
+            assert!(error.file.ends_with("search.json"));
+            assert_eq!(error.path, "[0].source");
+            assert_eq!(
+                error.reason, "duplicate of the source of payments.policy: line 2 column 1");
+
+            // Files sharing a source are merged by default
+            let db = load(None).unwrap();
+            assert_eq!(db.get_report().files.len(), 2);
+            assert_eq!(
+                db.get_report().duplicates[0].to_string(),
+                "source name 'gateway' of search.json: [0] merged into \
+                 payments.policy: line 2 column 1");
+            assert_eq!(
+                db.get_report().to_string(),
+                "1 source entries with 2 destination entries from 2 files, \
+                 1 duplicate sources merged, 0 overwritten");
+            let gateway = Identifier::from_given("gateway", 1);
+            let evaluate = |to: &str| {
+                db.is_call_allowed(&call(&gateway, &Identifier::from_given(to, 2), b"", false))
+            };
+            assert_eq!(evaluate("ledger").matched_rule.unwrap(), "payments.policy: line 2");
+            assert_eq!(
+                evaluate("search").matched_rule.unwrap(),
+                "search.json: rules[0].destinations[0]");
+
+            write("search.json", r#"{"groups": {"payments": ["search"]}}"#);
+            let error = load(None).unwrap_err();
+            assert!(error.file.ends_with("search.json"));
+            assert_eq!(error.path, "groups.payments");
+            assert!(
+                error.reason.starts_with("group 'payments' is already defined in "),
+                "{}", error);
+        });
+        std::fs::remove_dir_all(directory).expect("Cannot remove test directory");
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn test_duplicate_sources() {
+        let config_name = "test_duplicate_sources_cfg.json";
+        let config = r#"
+[
+    { "source": { "name": "a" }, "destinations": [ { "name": "b" } ] },
+    { "source": { "name": "a", "pid": 1 }, "destinations": [ { "name": "b" } ] },
+    { "source": { "name": "a" }, "destinations": [ { "name": "c" } ] },
+    { "source": { "name_regex": "a" }, "destinations": [ { "name": "d" } ] },
+    { "source": { "name": "a" }, "destinations": [ { "name": "e" } ] }
+]
+            "#;
+        run_test_with_config(|| {
+            let load = |duplicates| RulesDatabase::load(
+                std::path::Path::new(config_name), Default::default(),
+                LoadOptions { duplicates, match_pid: true, ..Default::default() });
+            let allowed = |db: &RulesDatabase, to: &str| {
+                let from = Identifier::from_given("a", 2);
+                let to = Identifier::from_given(to, 3);
+                db.is_call_allowed(&call(&from, &to, b"", false)).decision == Decision::ALLOW
+            };
+
+            let error = load(Some(DuplicatePolicy::Error)).unwrap_err();
+            assert_eq!(error.path, "[2].source");
+            assert_eq!(error.reason, "duplicate of the source of [0]");
+
+            let db = load(Some(DuplicatePolicy::Merge)).unwrap();
+            assert!(["b", "c", "d", "e"].iter().all(|to| allowed(&db, to)));
+            let report = db.get_report();
+            assert_eq!((report.sources, report.destinations), (3, 5));
+            let duplicates: Vec<_> = report.duplicates.iter().map(|d| d.to_string()).collect();
+            assert_eq!(duplicates, [
+                "source name 'a' of [2] merged into [0]",
+                "source name 'a' of [4] merged into [0]",
+            ]);
+            assert_eq!(
+                report.to_string(),
+                "3 source entries with 5 destination entries, \
+                 2 duplicate sources merged, 0 overwritten");
+
+            // The last entry of a file wins by default
+            let db = load(None).unwrap();
+            assert!(!allowed(&db, "b") && !allowed(&db, "c"));
+            assert!(allowed(&db, "d") && allowed(&db, "e"));
+            let duplicates: Vec<_> = db.get_report().duplicates.iter()
+                .map(|d| d.to_string())
+                .collect();
+            assert_eq!(duplicates, [
+                "source name 'a' of [2] overwrote [0]",
+                "source name 'a' of [4] overwrote [2]",
+            ]);
+        }, config_name, config);
+    }
+
+    #[test]
+    fn test_batch() {
+        let config_name = "test_batch_cfg.json";
+        run_test(|| {
+            let id1 = Identifier::from_given("process1", 111);
+            let id2 = Identifier::from_given("process2", 222);
+            let id3 = Identifier::from_given("process3", 333);
+            let id4 = Identifier::from_given("process4", 444);
+            let db = load(config_name);
+            let calls = vec![
+                call(&id1, &id2, b"", false), call(&id1, &id4, b"", true),
+                call(&id1, &id3, b"", false), call(&id4, &id1, b"", false),
+                call(&id2, &id1, b"", true), call(&id2, &id3, b"", false),
+            ];
+            let expected: Vec<_> = calls.iter()
+                .map(|call| db.is_call_allowed(call))
+                .collect();
+            assert_eq!(db.are_calls_allowed(calls), expected);
+            assert!(db.are_calls_allowed(Vec::new()).is_empty());
         }, config_name);
     }
 }
@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use tinyjson::JsonValue;
+
+use crate::policy::Locations;
+use crate::rules_database::RulesError;
+use crate::rules_format::RulesFormat;
+
+
+/// Rules file parsed into the JSON model
+#[derive(Debug)]
+pub struct Document {
+    pub file: PathBuf,
+    pub format: RulesFormat,
+    pub value: JsonValue,
+    /// Positions of the values in the file, for formats which keep them
+    pub locations: Locations,
+}
+
+
+impl Document {
+    /// Replaces the JSON path of an error found in this document by the
+    /// position of the invalid value, if known
+    pub fn locate(&self, mut error: RulesError) -> RulesError {
+        error.path = self.position(&error.path);
+        error
+    }
+
+    /// Describes a value of this document by its position, if known, or by
+    /// its JSON path
+    pub fn position(&self, path: &str) -> String {
+        match self.locations.locate(path) {
+            Some((line, column)) => format!("line {} column {}", line, column),
+            None => path.to_owned(),
+        }
+    }
+}
+
+
+/// Reads a rules file, or all the rules files of a directory in the order
+/// of their names, along with the files they include
+///
+/// Files list the files or directories they include, relative to their own
+/// directory, by the `include` string or array of strings of the top-level
+/// object. Each file follows the file which includes it first and is read
+/// once, however many times it is included.
+pub fn read(path: &Path, format: Option<RulesFormat>) -> Result<Vec<Document>, RulesError> {
+    let mut reader = Reader { format, documents: Vec::new(), read: HashSet::new() };
+    let path = path.canonicalize().map_err(|e| RulesError {
+        file: path.to_owned(), path: String::new(), reason: e.to_string(),
+    })?;
+    reader.read_path(&path)?;
+    Ok(reader.documents)
+}
+
+
+struct Reader {
+    format: Option<RulesFormat>,
+    documents: Vec<Document>,
+    /// Canonical paths of the files read so far
+    read: HashSet<PathBuf>,
+}
+
+
+impl Reader {
+    fn read_path(&mut self, path: &Path) -> Result<(), RulesError> {
+        let error = |reason: String| RulesError {
+            file: path.to_owned(), path: String::new(), reason,
+        };
+        if !path.is_dir() {
+            return self.read_file(path);
+        }
+        let mut files = std::fs::read_dir(path)
+            .and_then(|entries| entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>())
+            .map_err(|e| error(e.to_string()))?;
+        // Hidden files are left to editors and version control
+        files.retain(|file| {
+            let hidden = file.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.'));
+            file.is_file() && !hidden && RulesFormat::from_extension(file).is_some()
+        });
+        files.sort();
+        if files.is_empty() {
+            return Err(error("no rules files in the directory".to_owned()));
+        }
+        for file in files {
+            let file = file.canonicalize().map_err(|e| error(e.to_string()))?;
+            self.read_file(&file)?;
+        }
+        Ok(())
+    }
+
+    fn read_file(&mut self, file: &Path) -> Result<(), RulesError> {
+        if !self.read.insert(file.to_owned()) {
+            log::debug!("Rules file {:?} is already read", file);
+            return Ok(());
+        }
+        let error = |reason: String| RulesError {
+            file: file.to_owned(), path: String::new(), reason,
+        };
+        let data = std::fs::read_to_string(file).map_err(|e| error(e.to_string()))?;
+        log::info!{"Use rules file: {:?}", file};
+
+        let format = self.format.unwrap_or_else(|| RulesFormat::from_path(file));
+        let (value, locations) = format.parse(&data).map_err(error)?;
+        let document = Document { file: file.to_owned(), format, value, locations };
+        let includes = includes(&document)?;
+        self.documents.push(document);
+
+        let directory = file.parent().unwrap_or_else(|| Path::new("/"));
+        for (path, include) in includes {
+            let included = directory.join(&include).canonicalize().map_err(|e| {
+                let document = self.documents.iter()
+                    .find(|document| document.file == file)
+                    .expect("Including document should be read");
+                document.locate(RulesError {
+                    file: file.to_owned(),
+                    path,
+                    reason: format!("cannot include '{}': {}", include, e),
+                })
+            })?;
+            self.read_path(&included)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Lists the paths included by the document with their JSON paths
+fn includes(document: &Document) -> Result<Vec<(String, String)>, RulesError> {
+    let invalid = |path: &str| document.locate(RulesError {
+        file: document.file.clone(),
+        path: path.to_owned(),
+        reason: "should be a path or an array of paths".to_owned(),
+    });
+    let include = match document.value.get::<HashMap<String, JsonValue>>() {
+        Some(object) => match object.get("include") {
+            Some(include) => include,
+            None => return Ok(Vec::new()),
+        },
+        None => return Ok(Vec::new()),
+    };
+    match include {
+        JsonValue::String(path) => Ok(vec![("include".to_owned(), path.clone())]),
+        JsonValue::Array(paths) => paths.iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let json_path = format!("include[{}]", i);
+                match path.get::<String>() {
+                    Some(path) => Ok((json_path, path.clone())),
+                    None => Err(invalid(&json_path)),
+                }
+            })
+            .collect(),
+        _ => Err(invalid("include")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::rules_files::read;
+
+    #[test]
+    fn test_read() {
+        let directory = Path::new("test_read_rules");
+        let write = |name: &str, text: &str| {
+            std::fs::write(directory.join(name), text).expect("Cannot write test rules file")
+        };
+        std::fs::create_dir_all(directory.join("shared")).expect("Cannot create test directory");
+        let result = std::panic::catch_unwind(|| {
+            write("b.yaml", "include: [shared, a.json]\nrules: []\n");
+            write("a.json", r#"{"include": "shared/common.policy", "rules": []}"#);
+            write("notes.txt", "not rules");
+            write(".hidden.json", "[");
+            write("shared/common.policy", "include \"../b.yaml\"\nallow a -> b\n");
+
+            let root = directory.canonicalize().unwrap();
+            let files: Vec<_> = read(directory, None).unwrap().into_iter()
+                .map(|document| document.file.strip_prefix(&root).unwrap().to_owned())
+                .collect();
+            assert_eq!(files, [
+                Path::new("a.json"), Path::new("shared/common.policy"), Path::new("b.yaml"),
+            ]);
+
+            write("shared/common.policy", "allow a -> b\ninclude \"missing.json\"\n");
+            let error = read(directory, None).unwrap_err();
+            assert!(error.file.ends_with("shared/common.policy"));
+            assert_eq!(error.path, "line 2 column 9");
+            assert!(error.reason.starts_with("cannot include 'missing.json': "), "{}", error);
+
+            write("a.json", r#"{"include": [1], "rules": []}"#);
+            assert_eq!(read(directory, None).unwrap_err().path, "include[0]");
+        });
+        std::fs::remove_dir_all(directory).expect("Cannot remove test directory");
+        assert!(result.is_ok())
+    }
+}
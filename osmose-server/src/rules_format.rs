@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use tinyjson::JsonValue;
+
+use crate::policy::{self, Locations};
+
+
+/// Format of a rules file
+///
+/// YAML and TOML files are converted into the same JSON model, so all the
+/// formats describe rules with the same keys and report invalid values by
+/// the same paths. Policies are compiled into the model as well, with their
+/// invalid values reported by line and column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RulesFormat {
+    Json,
+    Yaml,
+    /// TOML, whose top level is a table, so rules are given by the `rules`
+    /// array of tables
+    Toml,
+    /// Policy language described by `policy::compile`
+    Policy,
+}
+
+
+impl std::str::FromStr for RulesFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(RulesFormat::Json),
+            "yaml" | "yml" => Ok(RulesFormat::Yaml),
+            "toml" => Ok(RulesFormat::Toml),
+            "policy" => Ok(RulesFormat::Policy),
+            _ => Err(format!(
+                "unknown rules format '{}', expected json, yaml, toml or policy", format)),
+        }
+    }
+}
+
+
+impl fmt::Display for RulesFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RulesFormat::Json => write!(f, "JSON"),
+            RulesFormat::Yaml => write!(f, "YAML"),
+            RulesFormat::Toml => write!(f, "TOML"),
+            RulesFormat::Policy => write!(f, "policy"),
+        }
+    }
+}
+
+
+impl RulesFormat {
+    /// Guesses the format by the extension of the file, which is JSON
+    /// unless it is `.yaml`, `.yml`, `.toml` or `.policy`
+    pub fn from_path(path: &Path) -> RulesFormat {
+        RulesFormat::from_extension(path).unwrap_or(RulesFormat::Json)
+    }
+
+    /// Finds the format by the extension of the file, if it is one of
+    /// `.json`, `.yaml`, `.yml`, `.toml` or `.policy`
+    pub fn from_extension(path: &Path) -> Option<RulesFormat> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| extension.to_ascii_lowercase().parse().ok())
+    }
+
+    /// Parses the text into the JSON model of the rules, along with the
+    /// positions of its values if the format keeps track of them
+    pub fn parse(self, text: &str) -> Result<(JsonValue, Locations), String> {
+        let invalid = |e: String| format!("invalid {}: {}", self, e);
+        let value = match self {
+            RulesFormat::Json => text.parse()
+                .map_err(|e: tinyjson::JsonParseError| invalid(e.to_string()))?,
+            RulesFormat::Yaml => {
+                let value = serde_yaml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+                from_yaml(value, "").map_err(invalid)?
+            },
+            RulesFormat::Toml => {
+                let value = text.parse::<toml::Table>().map_err(|e| {
+                    let message = e.message().trim_end();
+                    invalid(match e.span() {
+                        Some(span) => format!("{} at {}", message, location(text, span.start)),
+                        None => message.to_owned(),
+                    })
+                })?;
+                from_toml(toml::Value::Table(value))
+            },
+            RulesFormat::Policy => return policy::compile(text).map_err(invalid),
+        };
+        Ok((value, Locations::default()))
+    }
+}
+
+
+/// Describes the position of a byte offset as a line and a column
+fn location(text: &str, offset: usize) -> String {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    format!("line {} column {}", line, column)
+}
+
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) }
+}
+
+
+fn from_yaml(value: serde_yaml::Value, path: &str) -> Result<JsonValue, String> {
+    use serde_yaml::Value;
+    Ok(match value {
+        Value::Null => JsonValue::Null,
+        Value::Bool(value) => JsonValue::Boolean(value),
+        Value::Number(number) => match number.as_f64() {
+            Some(number) => JsonValue::Number(number),
+            None => return Err(format!("{}: {} is not a number", path, number)),
+        },
+        Value::String(value) => JsonValue::String(value),
+        Value::Sequence(values) => JsonValue::Array(values.into_iter()
+            .enumerate()
+            .map(|(i, value)| from_yaml(value, &format!("{}[{}]", path, i)))
+            .collect::<Result<_, _>>()?),
+        Value::Mapping(mapping) => JsonValue::Object(mapping.into_iter()
+            .map(|(key, value)| match key {
+                Value::String(key) => {
+                    let value = from_yaml(value, &join(path, &key))?;
+                    Ok((key, value))
+                },
+                key => Err(format!("{}: key {:?} should be a string", path, key)),
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?),
+        Value::Tagged(tagged) => {
+            return Err(format!("{}: tag {} is not supported", path, tagged.tag));
+        },
+    })
+}
+
+
+fn from_toml(value: toml::Value) -> JsonValue {
+    use toml::Value;
+    match value {
+        Value::String(value) => JsonValue::String(value),
+        Value::Integer(value) => JsonValue::Number(value as f64),
+        Value::Float(value) => JsonValue::Number(value),
+        Value::Boolean(value) => JsonValue::Boolean(value),
+        Value::Datetime(value) => JsonValue::String(value.to_string()),
+        Value::Array(values) => JsonValue::Array(values.into_iter().map(from_toml).collect()),
+        Value::Table(table) => JsonValue::Object(table.into_iter()
+            .map(|(key, value)| (key, from_toml(value)))
+            .collect()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::rules_format::RulesFormat;
+
+    #[test]
+    fn test_formats_agree() {
+        let json = r#"
+{"rules": [{"source": {"name": "a", "pid": 1}, "destinations": [{"name": "b"}]}]}
+"#;
+        let yaml = "
+# Comments are allowed
+rules:
+  - source: { name: a, pid: 1 }
+    destinations:
+      - name: b
+";
+        let toml = r#"
+# Comments are allowed
+[[rules]]
+source = { name = "a", pid = 1 }
+destinations = [{ name = "b" }]
+"#;
+        let parse = |format: RulesFormat, text| format.parse(text).unwrap().0;
+        let expected = parse(RulesFormat::Json, json);
+        assert_eq!(parse(RulesFormat::Yaml, yaml), expected);
+        assert_eq!(parse(RulesFormat::Toml, toml), expected);
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(RulesFormat::from_path(Path::new("rules.yaml")), RulesFormat::Yaml);
+        assert_eq!(RulesFormat::from_path(Path::new("rules.YML")), RulesFormat::Yaml);
+        assert_eq!(RulesFormat::from_path(Path::new("rules.toml")), RulesFormat::Toml);
+        assert_eq!(RulesFormat::from_path(Path::new("rules.json")), RulesFormat::Json);
+        assert_eq!(RulesFormat::from_path(Path::new("rules.policy")), RulesFormat::Policy);
+        assert_eq!(RulesFormat::from_path(Path::new("rules")), RulesFormat::Json);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = RulesFormat::Toml.parse("[[rules]]\nsource = \n").unwrap_err();
+        assert!(error.starts_with("invalid TOML: "), "{}", error);
+        assert!(error.ends_with("at line 2 column 10"), "{}", error);
+        let error = RulesFormat::Yaml.parse("rules:\n  - [a\n").unwrap_err();
+        assert!(error.starts_with("invalid YAML: ") && error.contains("line"), "{}", error);
+        assert_eq!(
+            RulesFormat::Yaml.parse("rules:\n  - 1: a\n").unwrap_err(),
+            "invalid YAML: rules[0]: key Number(1) should be a string");
+        assert_eq!(
+            RulesFormat::Yaml.parse("rules: !include other.yaml\n").unwrap_err(),
+            "invalid YAML: rules: tag !include is not supported");
+    }
+}
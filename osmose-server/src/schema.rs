@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use protobuf::Message;
+use protobuf::descriptor::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto_Label,
+    FieldDescriptorProto_Type, FileDescriptorSet,
+};
+
+use crate::wire::{WireFields, WireValue};
+
+
+/// Maximum nesting depth of messages checked by `SchemaRegistry::validate`
+const MAX_DEPTH: usize = 64;
+
+
+/// Message types known to the server, loaded from `FileDescriptorSet` files
+/// such as produced by `protoc --include_imports --descriptor_set_out`
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    messages: HashMap<String, MessageSchema>,
+    enums: HashMap<String, HashMap<String, i32>>,
+}
+
+
+#[derive(Debug)]
+struct MessageSchema {
+    fields: Vec<FieldSchema>,
+}
+
+
+/// Field of a registered message type
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub number: u32,
+    pub kind: FieldKind,
+    pub repeated: bool,
+}
+
+
+/// Type of a field, with fully-qualified names for message and enum types
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Double,
+    Float,
+    Int64,
+    UInt64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Bytes,
+    UInt32,
+    SFixed32,
+    SFixed64,
+    SInt32,
+    SInt64,
+    Group,
+    Message(String),
+    Enum(String),
+}
+
+
+/// Value of a decoded field
+///
+/// Integer fields of all encodings and enum fields are `Int`.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldKind::Message(name) | FieldKind::Enum(name) => write!(f, "{}", name),
+            other => write!(f, "{}", format!("{:?}", other).to_lowercase()),
+        }
+    }
+}
+
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(number) => write!(f, "{}", number),
+            Value::Float(number) => write!(f, "{}", number),
+            Value::Bool(flag) => write!(f, "{}", flag),
+            Value::Text(text) => write!(f, "{:?}", text),
+            Value::Bytes(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            },
+        }
+    }
+}
+
+
+impl FieldKind {
+    /// Whether repeated fields of this type may use packed encoding
+    fn is_packable(&self) -> bool {
+        !matches!(
+            self,
+            FieldKind::String | FieldKind::Bytes | FieldKind::Group | FieldKind::Message(_))
+    }
+
+    /// Value of the field when it is not present in the message
+    pub fn default_value(&self) -> Value {
+        match self {
+            FieldKind::Double | FieldKind::Float => Value::Float(0.0),
+            FieldKind::Bool => Value::Bool(false),
+            FieldKind::String => Value::Text(String::new()),
+            FieldKind::Bytes | FieldKind::Group | FieldKind::Message(_) => Value::Bytes(Vec::new()),
+            _ => Value::Int(0),
+        }
+    }
+}
+
+
+impl FieldSchema {
+    /// Interprets a raw value according to the type of the field
+    fn to_value(&self, value: WireValue) -> Result<Value, String> {
+        let value = match (&self.kind, value) {
+            (FieldKind::Int64, WireValue::Varint(v)) => Value::Int(i128::from(v as i64)),
+            (FieldKind::UInt64, WireValue::Varint(v)) => Value::Int(i128::from(v)),
+            (FieldKind::Int32, WireValue::Varint(v))
+            | (FieldKind::Enum(_), WireValue::Varint(v)) => Value::Int(i128::from(v as i32)),
+            (FieldKind::UInt32, WireValue::Varint(v)) => Value::Int(i128::from(v as u32)),
+            (FieldKind::SInt64, WireValue::Varint(v)) => Value::Int(i128::from(zigzag(v))),
+            (FieldKind::SInt32, WireValue::Varint(v)) => {
+                Value::Int(i128::from(zigzag(u64::from(v as u32))))
+            },
+            (FieldKind::Bool, WireValue::Varint(v)) => Value::Bool(v != 0),
+            (FieldKind::Double, WireValue::Fixed64(v)) => Value::Float(f64::from_bits(v)),
+            (FieldKind::Fixed64, WireValue::Fixed64(v)) => Value::Int(i128::from(v)),
+            (FieldKind::SFixed64, WireValue::Fixed64(v)) => Value::Int(i128::from(v as i64)),
+            (FieldKind::Float, WireValue::Fixed32(v)) => {
+                Value::Float(f64::from(f32::from_bits(v)))
+            },
+            (FieldKind::Fixed32, WireValue::Fixed32(v)) => Value::Int(i128::from(v)),
+            (FieldKind::SFixed32, WireValue::Fixed32(v)) => Value::Int(i128::from(v as i32)),
+            (FieldKind::String, WireValue::Bytes(bytes)) => Value::Text(
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|_| format!("field {} is not valid UTF-8", self.name))?),
+            (FieldKind::Bytes, WireValue::Bytes(bytes))
+            | (FieldKind::Message(_), WireValue::Bytes(bytes)) => Value::Bytes(bytes.to_vec()),
+            (kind, value) => return Err(format!(
+                "field {} of type {} has {} encoding", self.name, kind, value.wire_type())),
+        };
+        Ok(value)
+    }
+}
+
+
+impl SchemaRegistry {
+    /// Loads message types from binary `FileDescriptorSet` files
+    pub fn load<'a, I>(paths: I) -> Result<SchemaRegistry, String>
+    where I: IntoIterator<Item = &'a Path>
+    {
+        let mut registry = SchemaRegistry::default();
+        for path in paths {
+            let data = std::fs::read(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            let set = FileDescriptorSet::parse_from_bytes(&data)
+                .map_err(|e| format!("{}: not a FileDescriptorSet: {}", path.display(), e))?;
+            registry.add_descriptor_set(&set);
+            log::info!("Loaded descriptor set {:?}", path);
+        }
+        Ok(registry)
+    }
+
+    /// Registers all message and enum types of the descriptor set
+    ///
+    /// Types registered before under the same name are replaced.
+    pub fn add_descriptor_set(&mut self, set: &FileDescriptorSet) {
+        for file in set.get_file() {
+            let scope = match file.get_package() {
+                "" => String::new(),
+                package => format!("{}.", package),
+            };
+            for message in file.get_message_type() {
+                self.add_message(&scope, message);
+            }
+            for enumeration in file.get_enum_type() {
+                self.add_enum(&scope, enumeration);
+            }
+        }
+    }
+
+    fn add_message(&mut self, scope: &str, message: &DescriptorProto) {
+        let name = format!("{}{}", scope, message.get_name());
+        let fields = message.get_field()
+            .iter()
+            .map(|field| FieldSchema {
+                name: field.get_name().to_owned(),
+                number: field.get_number() as u32,
+                kind: field_kind(field.get_field_type(), field.get_type_name()),
+                repeated: field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED,
+            })
+            .collect();
+        let nested_scope = format!("{}.", name);
+        for nested in message.get_nested_type() {
+            self.add_message(&nested_scope, nested);
+        }
+        for enumeration in message.get_enum_type() {
+            self.add_enum(&nested_scope, enumeration);
+        }
+        self.messages.insert(name, MessageSchema { fields });
+    }
+
+    fn add_enum(&mut self, scope: &str, enumeration: &EnumDescriptorProto) {
+        let values = enumeration.get_value()
+            .iter()
+            .map(|value| (value.get_name().to_owned(), value.get_number()))
+            .collect();
+        self.enums.insert(format!("{}{}", scope, enumeration.get_name()), values);
+    }
+
+    /// Whether a message type with the given fully-qualified name is known
+    pub fn has_message(&self, name: &str) -> bool {
+        self.messages.contains_key(name)
+    }
+
+    /// Returns the number of a value of an enum type
+    pub fn enum_value(&self, enumeration: &str, value: &str) -> Option<i32> {
+        self.enums.get(enumeration)?.get(value).copied()
+    }
+
+    /// Resolves a dotted path of field names, starting from the given
+    /// message type, into the fields it goes through
+    ///
+    /// Every field but the last one has to be a message, and repeated
+    /// fields cannot be part of a path.
+    pub fn resolve(&self, message: &str, path: &str) -> Result<Vec<FieldSchema>, String> {
+        let mut fields: Vec<FieldSchema> = Vec::new();
+        for name in path.split('.') {
+            let current = match fields.last() {
+                None => message,
+                Some(FieldSchema { kind: FieldKind::Message(type_name), .. }) => type_name,
+                Some(field) => return Err(format!(
+                    "field '{}' of type {} is not a message", field.name, field.kind)),
+            };
+            let schema = self.messages.get(current)
+                .ok_or_else(|| format!("unknown message type '{}'", current))?;
+            let field = schema.fields.iter()
+                .find(|field| field.name == name)
+                .ok_or_else(|| format!("message '{}' has no field '{}'", current, name))?;
+            if field.repeated {
+                return Err(format!("repeated field '{}' cannot be used in a path", name));
+            }
+            if field.kind == FieldKind::Group {
+                return Err(format!("group field '{}' is not supported", name));
+            }
+            fields.push(field.clone());
+        }
+        Ok(fields)
+    }
+
+    /// Checks that the payload decodes as the given message type
+    ///
+    /// Fields not described by the schema are skipped, as a newer version of
+    /// the message might have added them. Nested messages of types missing
+    /// from the registry are not checked.
+    pub fn validate(&self, message: &str, payload: &[u8]) -> Result<(), String> {
+        self.validate_message(message, payload, 0)
+    }
+
+    fn validate_message(&self, message: &str, payload: &[u8], depth: usize) -> Result<(), String> {
+        let schema = match self.messages.get(message) {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        if depth == MAX_DEPTH {
+            return Err(format!("messages are nested deeper than {} levels", MAX_DEPTH));
+        }
+        for field in WireFields::new(payload) {
+            let (number, value) = field?;
+            let field = match schema.fields.iter().find(|field| field.number == number) {
+                Some(field) => field,
+                None => continue,
+            };
+            match (&field.kind, value) {
+                (FieldKind::Message(type_name), WireValue::Bytes(bytes)) => {
+                    self.validate_message(type_name, bytes, depth + 1)?;
+                },
+                (kind, WireValue::Bytes(_)) if field.repeated && kind.is_packable() => (),
+                _ => {
+                    field.to_value(value)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Reads the field at the end of a path resolved by `SchemaRegistry::resolve`
+///
+/// Fields missing from the payload, or lying in a missing message, have
+/// their default value. A field which occurs several times has the value of
+/// its last occurrence.
+pub fn decode(path: &[FieldSchema], payload: &[u8]) -> Result<Value, String> {
+    let mut message = payload;
+    for (depth, field) in path.iter().enumerate() {
+        let mut found = None;
+        for entry in WireFields::new(message) {
+            let (number, value) = entry?;
+            if number == field.number {
+                found = Some(value);
+            }
+        }
+        match found {
+            None => break,
+            Some(value) if depth + 1 == path.len() => return field.to_value(value),
+            Some(WireValue::Bytes(bytes)) => message = bytes,
+            Some(value) => return Err(format!(
+                "field {} of type {} has {} encoding",
+                field.name, field.kind, value.wire_type())),
+        }
+    }
+    let leaf = path.last().ok_or("empty field path")?;
+    Ok(leaf.kind.default_value())
+}
+
+
+fn field_kind(field_type: FieldDescriptorProto_Type, type_name: &str) -> FieldKind {
+    // Type names in descriptor sets are fully qualified with a leading dot
+    let type_name = type_name.trim_start_matches('.').to_owned();
+    match field_type {
+        FieldDescriptorProto_Type::TYPE_DOUBLE => FieldKind::Double,
+        FieldDescriptorProto_Type::TYPE_FLOAT => FieldKind::Float,
+        FieldDescriptorProto_Type::TYPE_INT64 => FieldKind::Int64,
+        FieldDescriptorProto_Type::TYPE_UINT64 => FieldKind::UInt64,
+        FieldDescriptorProto_Type::TYPE_INT32 => FieldKind::Int32,
+        FieldDescriptorProto_Type::TYPE_FIXED64 => FieldKind::Fixed64,
+        FieldDescriptorProto_Type::TYPE_FIXED32 => FieldKind::Fixed32,
+        FieldDescriptorProto_Type::TYPE_BOOL => FieldKind::Bool,
+        FieldDescriptorProto_Type::TYPE_STRING => FieldKind::String,
+        FieldDescriptorProto_Type::TYPE_GROUP => FieldKind::Group,
+        FieldDescriptorProto_Type::TYPE_MESSAGE => FieldKind::Message(type_name),
+        FieldDescriptorProto_Type::TYPE_BYTES => FieldKind::Bytes,
+        FieldDescriptorProto_Type::TYPE_UINT32 => FieldKind::UInt32,
+        FieldDescriptorProto_Type::TYPE_ENUM => FieldKind::Enum(type_name),
+        FieldDescriptorProto_Type::TYPE_SFIXED32 => FieldKind::SFixed32,
+        FieldDescriptorProto_Type::TYPE_SFIXED64 => FieldKind::SFixed64,
+        FieldDescriptorProto_Type::TYPE_SINT32 => FieldKind::SInt32,
+        FieldDescriptorProto_Type::TYPE_SINT64 => FieldKind::SInt64,
+    }
+}
+
+
+fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+
+/// Registry with the types used by tests:
+///
+/// ```proto
+/// package payments;
+/// enum Kind { UNKNOWN = 0; FULL = 1; PARTIAL = 2; }
+/// message Header { string caller = 1; sint32 priority = 2; }
+/// message Refund {
+///   string method = 1; int64 amount = 2; Header header = 3;
+///   Kind kind = 4; repeated int32 tags = 5; double ratio = 6;
+/// }
+/// ```
+#[cfg(test)]
+pub fn test_registry() -> SchemaRegistry {
+    use protobuf::descriptor::{
+        EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    };
+
+    fn field(
+        name: &str, number: i32, field_type: FieldDescriptorProto_Type, type_name: &str
+    ) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_number(number);
+        field.set_field_type(field_type);
+        field.set_type_name(type_name.to_owned());
+        field.set_label(FieldDescriptorProto_Label::LABEL_OPTIONAL);
+        field
+    }
+
+    let mut kind = EnumDescriptorProto::new();
+    kind.set_name("Kind".to_owned());
+    for (number, name) in ["UNKNOWN", "FULL", "PARTIAL"].iter().enumerate() {
+        let mut value = EnumValueDescriptorProto::new();
+        value.set_name((*name).to_owned());
+        value.set_number(number as i32);
+        kind.mut_value().push(value);
+    }
+
+    let mut header = DescriptorProto::new();
+    header.set_name("Header".to_owned());
+    header.mut_field().push(field("caller", 1, FieldDescriptorProto_Type::TYPE_STRING, ""));
+    header.mut_field().push(field("priority", 2, FieldDescriptorProto_Type::TYPE_SINT32, ""));
+
+    let mut refund = DescriptorProto::new();
+    refund.set_name("Refund".to_owned());
+    refund.mut_field().push(field("method", 1, FieldDescriptorProto_Type::TYPE_STRING, ""));
+    refund.mut_field().push(field("amount", 2, FieldDescriptorProto_Type::TYPE_INT64, ""));
+    refund.mut_field().push(
+        field("header", 3, FieldDescriptorProto_Type::TYPE_MESSAGE, ".payments.Header"));
+    refund.mut_field().push(
+        field("kind", 4, FieldDescriptorProto_Type::TYPE_ENUM, ".payments.Kind"));
+    let mut tags = field("tags", 5, FieldDescriptorProto_Type::TYPE_INT32, "");
+    tags.set_label(FieldDescriptorProto_Label::LABEL_REPEATED);
+    refund.mut_field().push(tags);
+    refund.mut_field().push(field("ratio", 6, FieldDescriptorProto_Type::TYPE_DOUBLE, ""));
+
+    let mut file = FileDescriptorProto::new();
+    file.set_name("payments.proto".to_owned());
+    file.set_package("payments".to_owned());
+    file.mut_enum_type().push(kind);
+    file.mut_message_type().push(header);
+    file.mut_message_type().push(refund);
+
+    let mut set = FileDescriptorSet::new();
+    set.mut_file().push(file);
+    let mut registry = SchemaRegistry::default();
+    registry.add_descriptor_set(&set);
+    registry
+}
+
+
+/// `payments.Refund` with method "Delete", amount 1500, header with caller
+/// "process1" and priority -2, kind PARTIAL and packed tags 1 and 2
+#[cfg(test)]
+pub fn test_refund() -> Vec<u8> {
+    let mut payload = vec![0x0a, 0x06];
+    payload.extend_from_slice(b"Delete");
+    payload.extend_from_slice(&[0x10, 0xdc, 0x0b, 0x1a, 0x0c, 0x0a, 0x08]);
+    payload.extend_from_slice(b"process1");
+    payload.extend_from_slice(&[0x10, 0x03, 0x20, 0x02, 0x2a, 0x02, 0x01, 0x02]);
+    payload
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{decode, test_refund, test_registry, FieldKind, Value};
+
+    #[test]
+    fn test_resolve() {
+        let registry = test_registry();
+        assert!(registry.has_message("payments.Refund"));
+        assert!(!registry.has_message("Refund"));
+        let path = registry.resolve("payments.Refund", "header.priority").unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[1].kind, FieldKind::SInt32);
+        assert_eq!(registry.enum_value("payments.Kind", "PARTIAL"), Some(2));
+
+        assert!(registry.resolve("payments.Refund", "missing").is_err());
+        assert!(registry.resolve("payments.Refund", "tags").is_err());
+        assert!(registry.resolve("payments.Refund", "amount.value").is_err());
+        assert!(registry.resolve("payments.Missing", "amount").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let registry = test_registry();
+        let payload = test_refund();
+        let field = |path| decode(&registry.resolve("payments.Refund", path).unwrap(), &payload);
+        assert_eq!(field("method"), Ok(Value::Text("Delete".to_owned())));
+        assert_eq!(field("amount"), Ok(Value::Int(1500)));
+        assert_eq!(field("header.caller"), Ok(Value::Text("process1".to_owned())));
+        assert_eq!(field("header.priority"), Ok(Value::Int(-2)));
+        assert_eq!(field("kind"), Ok(Value::Int(2)));
+        assert_eq!(field("ratio"), Ok(Value::Float(0.0)));
+
+        let path = registry.resolve("payments.Refund", "header.caller").unwrap();
+        assert_eq!(decode(&path, &[]), Ok(Value::Text(String::new())));
+    }
+
+    #[test]
+    fn test_validate() {
+        let registry = test_registry();
+        assert_eq!(registry.validate("payments.Refund", &test_refund()), Ok(()));
+        assert_eq!(registry.validate("payments.Refund", &[0x78, 0x01]), Ok(()));
+        // amount with length-delimited encoding
+        assert!(registry.validate("payments.Refund", &[0x12, 0x01, 0x00]).is_err());
+        // caller in header is not valid UTF-8
+        assert!(registry.validate("payments.Refund", &[0x1a, 0x03, 0x0a, 0x01, 0xff]).is_err());
+        assert!(registry.validate("payments.Refund", &[0x0a, 0x10]).is_err());
+    }
+}
@@ -0,0 +1,783 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{watch, Semaphore};
+use tokio::time::{timeout, Instant, Sleep};
+use tokio_rustls::TlsAcceptor;
+
+use protobuf::Message;
+
+use crate::rules_database::{Call, Evaluation, RulesDatabase};
+use crate::tls::certificate_name;
+
+use osmose_generated::generated_proto::osmose::DecisionRequest as Request;
+use osmose_generated::generated_proto::osmose::DecisionResponse as Response;
+use osmose_generated::generated_proto::osmose::DecisionBatchRequest as BatchRequest;
+use osmose_generated::generated_proto::osmose::DecisionBatchResponse as BatchResponse;
+use osmose_generated::generated_proto::osmose::RequestEnvelope;
+use osmose_generated::generated_proto::osmose::RequestEnvelope_oneof_body as RequestBody;
+use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+use osmose_generated::generated_proto::osmose::Decision as Decision;
+use osmose_framing::{FrameError, read_frame_async, starts_with_frame, write_message_async};
+
+
+/// Size in bytes of the replies which may be held back on a connection
+/// before they are written out, even if further requests are buffered
+const MAX_HELD_REPLIES: usize = 64 * 1024;
+
+
+/// Pause after a failure to accept a connection, which keeps the server from
+/// spinning while it is out of file descriptors
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+
+/// Time a TLS client has to complete its handshake, whatever the idle
+/// timeout, so that clients which never finish it do not hold a connection
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Connection handling settings shared by all connections
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    /// Maximum size of a single request or reply frame
+    pub max_frame_size: usize,
+    /// Maximum number of connections served at the same time
+    pub max_connections: usize,
+    /// Time after which a connection without requests is closed
+    pub idle_timeout: Option<Duration>,
+    /// Whether identities claimed over Unix sockets are checked against the
+    /// credentials of the connected process
+    pub verify_peer: bool,
+}
+
+
+/// Credentials of the process on the other end of a Unix socket, as
+/// reported by the kernel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCredentials {
+    pub pid: Option<u32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+
+/// Description of a connected client
+#[derive(Debug)]
+struct Peer {
+    name: String,
+    /// Kernel-verified credentials, known only for Unix socket peers
+    credentials: Option<PeerCredentials>,
+    /// Common name of the verified client certificate, known only for
+    /// mutual TLS peers
+    certificate_name: Option<String>,
+}
+
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.credentials {
+            Some(PeerCredentials { pid: Some(pid), uid, gid }) => write!(
+                f, "{} (pid {}, uid {}, gid {})", self.name, pid, uid, gid),
+            Some(PeerCredentials { pid: None, uid, gid }) => write!(
+                f, "{} (uid {}, gid {})", self.name, uid, gid),
+            None => write!(f, "{}", self.name),
+        }?;
+        match &self.certificate_name {
+            Some(name) => write!(f, " (certificate '{}')", name),
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// Socket the server accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    /// TCP socket whose connections are wrapped in TLS
+    ///
+    /// If `client_auth` is set the acceptor requires client certificates, and
+    /// connections whose certificate has no common name are refused.
+    Tls { listener: TcpListener, acceptor: TlsAcceptor, client_auth: bool },
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+
+/// Accepts connections and serves each of them on its own task
+///
+/// At most as many connections as `limit` has permits are served at once,
+/// and the limit may be shared between several listeners. When the limit is
+/// reached the server stops accepting, so further clients wait in the listen
+/// backlog of the kernel until a connection is closed.
+///
+/// Each request is evaluated against the rules current when it is read, so
+/// reloaded rules apply to connections already open as well.
+pub async fn serve(
+    listener: Listener, rules: watch::Receiver<Arc<RulesDatabase>>, limit: Arc<Semaphore>,
+    config: ServerConfig
+) {
+    loop {
+        let permit = match limit.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log::warn!(
+                    "Connection limit of {} reached, waiting for a connection to close",
+                    config.max_connections);
+                limit.clone().acquire_owned().await
+                    .expect("Connection limit semaphore is never closed")
+            }
+        };
+        let rules = rules.clone();
+        match &listener {
+            Listener::Tcp(listener) => match listener.accept().await {
+                Ok((stream, address)) => {
+                    let peer = Peer {
+                        name: address.to_string(),
+                        credentials: None,
+                        certificate_name: None,
+                    };
+                    log::debug!("New OSMOSE connection: {}", peer);
+                    tokio::spawn(async move {
+                        handle_client(stream, peer, rules, config).await;
+                        drop(permit);
+                    });
+                }
+                Err(error) => {
+                    log::warn!("Stream error: {}", error);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            },
+            Listener::Tls { listener, acceptor, client_auth } => match listener.accept().await {
+                Ok((stream, address)) => {
+                    let acceptor = acceptor.clone();
+                    let client_auth = *client_auth;
+                    // The handshake runs on the connection task, so a slow
+                    // client does not hold up accepting others
+                    tokio::spawn(async move {
+                        let handshake = acceptor.accept(stream);
+                        let stream = match timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(error)) => {
+                                log::warn!("TLS handshake with {} failed: {}", address, error);
+                                return;
+                            },
+                            Err(_) => {
+                                log::warn!("TLS handshake with {} timed out", address);
+                                return;
+                            },
+                        };
+                        let certificate_name = stream.get_ref().1.peer_certificates()
+                            .and_then(|certificates| certificates.first())
+                            .and_then(certificate_name);
+                        if client_auth && certificate_name.is_none() {
+                            log::error!(
+                                "Client certificate of {} has no common name, \
+                                terminating connection", address);
+                            return;
+                        }
+                        let peer = Peer {
+                            name: address.to_string(),
+                            credentials: None,
+                            certificate_name,
+                        };
+                        log::debug!("New OSMOSE connection: {}", peer);
+                        handle_client(stream, peer, rules, config).await;
+                        drop(permit);
+                    });
+                }
+                Err(error) => {
+                    log::warn!("Stream error: {}", error);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => match listener.accept().await {
+                Ok((stream, _)) => {
+                    let credentials = if config.verify_peer {
+                        match stream.peer_cred() {
+                            Ok(credentials) => Some(PeerCredentials {
+                                pid: credentials.pid().map(|pid| pid as u32),
+                                uid: credentials.uid(),
+                                gid: credentials.gid(),
+                            }),
+                            Err(error) => {
+                                log::error!(
+                                    "Cannot get Unix socket peer credentials: {}", error);
+                                continue;
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    let peer = Peer {
+                        name: "Unix socket peer".to_owned(),
+                        credentials,
+                        certificate_name: None,
+                    };
+                    log::debug!("New OSMOSE connection: {}", peer);
+                    tokio::spawn(async move {
+                        handle_client(stream, peer, rules, config).await;
+                        drop(permit);
+                    });
+                }
+                Err(error) => {
+                    log::warn!("Stream error: {}", error);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            },
+        }
+    }
+}
+
+
+/// Serves a single connection until the peer closes it
+///
+/// A connection may carry any number of requests, single or batched, and
+/// each reply carries the `request_id` of its request. Requests are answered
+/// strictly in the order they were received, so clients are free to pipeline
+/// them.
+///
+/// Replies to pipelined requests are held back while further requests are
+/// already buffered, and written together once the buffer runs out of whole
+/// requests, which saves a write to the stream per request.
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S, peer: Peer, rules: watch::Receiver<Arc<RulesDatabase>>, config: ServerConfig
+) {
+    // Requests and replies alternate, so the stream is read and written
+    // through the same buffer without being split
+    let mut stream = BufReader::new(stream);
+    let mut idle = IdleTimer::new(config.idle_timeout);
+    let mut replies = Vec::new();
+    loop {
+        let mut keep_alive = true;
+        let frame = match idle.run(read_frame_async(&mut stream, config.max_frame_size)).await {
+            Some(frame) => frame,
+            None => {
+                log::debug!("Closing idle connection with {}", peer);
+                break;
+            }
+        };
+        let response = match frame {
+            Ok(data) => {
+                match RequestEnvelope::parse_from_bytes(&data) {
+                    Ok(envelope) => {
+                        let db = rules.borrow().clone();
+                        process_envelope(&envelope, &db, &peer)
+                    },
+                    Err(parse_error) => {
+                        log::error!(
+                            "Parse error: {}. Rejecting request from {}",
+                            parse_error, peer);
+                        error_response(
+                            Decision::MALFORMED_MESSAGE,
+                            format!("cannot parse request: {}", parse_error))
+                    }
+                }
+            },
+            Err(FrameError::Closed) => {
+                log::debug!("Connection closed by {}", peer);
+                break;
+            },
+            // TLS peers which go away without closing the session properly
+            Err(FrameError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                log::debug!("Connection with {} ended abruptly: {}", peer, e);
+                break;
+            },
+            Err(FrameError::TooLarge { size, max }) => {
+                log::error!(
+                    "Request of {} bytes exceeds maximum frame size of {} bytes, \
+                    terminating connection with {}",
+                    size, max, peer);
+                keep_alive = false;
+                error_response(
+                    Decision::MESSAGE_TOO_LARGE,
+                    format!("request of {} bytes exceeds maximum frame size of {} bytes",
+                            size, max))
+            },
+            Err(FrameError::InvalidLength) => {
+                log::error!(
+                    "Invalid frame length, terminating connection with {}", peer);
+                keep_alive = false;
+                error_response(
+                    Decision::MALFORMED_MESSAGE, "invalid frame length".to_owned())
+            },
+            Err(stream_error) => {
+                log::error!(
+                    "Stream error occurred: {}, terminating connection with {}",
+                    stream_error, peer);
+                break;
+            }
+        };
+        if let Err(e) = write_message_async(&mut replies, &response, config.max_frame_size).await {
+            log::error!("Failed to send reply to {}: {}", peer, e);
+            break;
+        }
+        if !keep_alive {
+            break;
+        }
+        if replies.len() < MAX_HELD_REPLIES && starts_with_frame(stream.buffer()) {
+            continue;
+        }
+        if let Err(e) = send_replies(&mut stream, &mut replies).await {
+            log::error!("Failed to send reply to {}: {}", peer, e);
+            break;
+        }
+    }
+    if let Err(e) = send_replies(&mut stream, &mut replies).await {
+        log::debug!("Cannot send replies to {}: {}", peer, e);
+    }
+    if let Err(e) = stream.shutdown().await {
+        log::debug!("Cannot shut down connection with {}: {}", peer, e);
+    }
+}
+
+
+/// Writes the replies held back to the stream
+async fn send_replies<S: AsyncWrite + Unpin>(
+    stream: &mut S, replies: &mut Vec<u8>
+) -> std::io::Result<()> {
+    if replies.is_empty() {
+        return Ok(());
+    }
+    stream.write_all(replies).await?;
+    replies.clear();
+    stream.flush().await
+}
+
+
+/// Tells when a connection has been idle for too long
+///
+/// A single timer serves the whole connection and is only moved forward
+/// when it expires, as arming a timer for every request shows up in the
+/// cost of serving it.
+struct IdleTimer {
+    timeout: Option<Duration>,
+    sleep: Pin<Box<Sleep>>,
+    last_active: Instant,
+}
+
+
+impl IdleTimer {
+    fn new(timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        IdleTimer {
+            timeout,
+            sleep: Box::pin(tokio::time::sleep_until(now + timeout.unwrap_or_default())),
+            last_active: now,
+        }
+    }
+
+    /// Waits for the future to complete, or returns `None` if the connection
+    /// has been idle for the timeout before that
+    async fn run<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Some(future.await),
+        };
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                biased;
+                output = &mut future => {
+                    self.last_active = Instant::now();
+                    return Some(output);
+                },
+                _ = self.sleep.as_mut() => {
+                    let deadline = self.last_active + timeout;
+                    if deadline <= Instant::now() {
+                        return None;
+                    }
+                    self.sleep.as_mut().reset(deadline);
+                },
+            }
+        }
+    }
+}
+
+
+fn process_envelope(
+    envelope: &RequestEnvelope, db: &RulesDatabase, peer: &Peer
+) -> ResponseEnvelope {
+    let mut response = ResponseEnvelope::new();
+    match &envelope.body {
+        Some(RequestBody::single(request)) => {
+            response.set_single(process_request(request, db, peer));
+        },
+        Some(RequestBody::batch(batch)) => {
+            response.set_batch(process_batch(batch, db, peer));
+        },
+        None => {
+            log::error!("Received an empty request envelope");
+            return error_response(
+                Decision::MALFORMED_MESSAGE, "empty request envelope".to_owned());
+        }
+    }
+    response
+}
+
+
+fn process_batch(
+    batch: &BatchRequest, db: &RulesDatabase, peer: &Peer
+) -> BatchResponse {
+    log::debug!(
+        "Processing batch {} of {} requests",
+        batch.get_request_id(), batch.get_requests().len());
+    let identifiers: Vec<_> = batch.get_requests()
+        .iter()
+        .map(|request| (
+            osmose_identifier::Identifier::from(request.get_source()),
+            osmose_identifier::Identifier::from(request.get_destination())
+        ))
+        .collect();
+    let evaluations = db.are_calls_allowed(
+        identifiers.iter()
+            .zip(batch.get_requests())
+            .map(|((from, to), request)| make_call(request, from, to))
+    );
+
+    let mut response = BatchResponse::new();
+    response.set_request_id(batch.get_request_id());
+    for (request, evaluation) in batch.get_requests().iter().zip(evaluations) {
+        let evaluation = check_identity(request, peer).unwrap_or(evaluation);
+        response.mut_responses().push(make_response(request, evaluation));
+    }
+    response
+}
+
+
+fn error_response(decision: Decision, reason: String) -> ResponseEnvelope {
+    let mut single = Response::new();
+    single.set_decision(decision);
+    single.set_reason(reason);
+    let mut response = ResponseEnvelope::new();
+    response.set_single(single);
+    response
+}
+
+
+fn process_request(
+    request: &Request, db: &RulesDatabase, peer: &Peer
+) -> Response {
+    log::debug!("Processing request {:?}", request);
+    if let Some(mismatch) = check_identity(request, peer) {
+        log::warn!(
+            "Identity mismatch in request {}: {}",
+            request.get_request_id(), mismatch.reason);
+        return make_response(request, mismatch);
+    }
+    let from = osmose_identifier::Identifier::from(request.get_source());
+    let to = osmose_identifier::Identifier::from(request.get_destination());
+    let evaluation = db.is_call_allowed(&make_call(request, &from, &to));
+
+    log::debug!(
+        "Verdict for request {} is {:?}: {}",
+        request.get_request_id(), evaluation.decision, evaluation.reason);
+
+    make_response(request, evaluation)
+}
+
+
+/// Processes a request the way a connection does, for a peer nothing is
+/// known about
+pub fn process_offline(request: &Request, db: &RulesDatabase) -> Response {
+    let peer = Peer { name: "offline check".to_owned(), credentials: None, certificate_name: None };
+    process_request(request, db, &peer)
+}
+
+
+/// Describes the call a request asks about
+///
+/// A payload sent as `google.protobuf.Any` is evaluated by its type URL,
+/// and message rules see the wrapped message.
+fn make_call<'a>(
+    request: &'a Request, from: &'a osmose_identifier::Identifier,
+    to: &'a osmose_identifier::Identifier
+) -> Call<'a> {
+    let (payload, type_url) = match request.typed_payload.as_ref() {
+        Some(typed) => (typed.get_value(), Some(typed.get_type_url())),
+        None => (request.get_payload(), None),
+    };
+    Call { from, to, payload, type_url, explain: request.get_explain() }
+}
+
+
+/// Checks the identity claimed in the request against what is known about
+/// the peer, returning the rejection if they do not match
+///
+/// The destination of a request is the entity asking for the verdict, so
+/// its name has to be the common name of the client certificate, and its id
+/// has to be the process id of a Unix socket peer. Whatever is unknown about
+/// the peer is not checked, so plain TCP requests are taken at their word.
+fn check_identity(request: &Request, peer: &Peer) -> Option<Evaluation> {
+    let claimed = request.get_destination();
+    if let Some(name) = &peer.certificate_name {
+        if name != claimed.get_name() {
+            return Some(identity_mismatch(format!(
+                "'{}' does not match the name '{}' in the client certificate",
+                claimed.get_name(), name)));
+        }
+    }
+    let credentials = peer.credentials?;
+    let reason = match credentials.pid {
+        Some(pid) if u64::from(pid) == claimed.get_id() => return None,
+        Some(pid) => format!(
+            "'{}' claims id {} but the connection belongs to pid {} (uid {}, gid {})",
+            claimed.get_name(), claimed.get_id(), pid, credentials.uid, credentials.gid),
+        None => format!(
+            "'{}' claims id {} but the pid of the connection is unknown (uid {}, gid {})",
+            claimed.get_name(), claimed.get_id(), credentials.uid, credentials.gid),
+    };
+    Some(identity_mismatch(reason))
+}
+
+
+fn identity_mismatch(reason: String) -> Evaluation {
+    Evaluation {
+        decision: Decision::IDENTITY_MISMATCH,
+        reason,
+        matched_rule: None,
+        trace: Vec::new(),
+    }
+}
+
+
+fn make_response(request: &Request, evaluation: Evaluation) -> Response {
+    let mut response = Response::new();
+    response.set_request_id(request.get_request_id());
+    response.set_decision(evaluation.decision);
+    response.set_reason(evaluation.reason);
+    if let Some(rule) = evaluation.matched_rule {
+        response.set_matched_rule(rule);
+    }
+    response.set_trace(evaluation.trace.into());
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{watch, Semaphore};
+    use tokio::time::timeout;
+
+    use osmose_generated::generated_proto::osmose::Decision as Decision;
+    use osmose_generated::generated_proto::osmose::DecisionRequest as Request;
+    use osmose_generated::generated_proto::osmose::DecisionBatchRequest as BatchRequest;
+    use osmose_generated::generated_proto::osmose::RequestEnvelope;
+    use osmose_generated::generated_proto::osmose::ResponseEnvelope;
+    use osmose_framing::{
+        read_message_async, write_message_async, FrameError, DEFAULT_MAX_FRAME_SIZE,
+    };
+    use osmose_identifier::Identifier;
+    use crate::rules_database::RulesDatabase;
+    use crate::server::{
+        check_identity, handle_client, process_envelope, serve, Listener, Peer, PeerCredentials,
+        ServerConfig,
+    };
+
+    const RULES: &str = r#"[{"source": {"name": "process1"}, "destinations": [{"name": "process2"}]}]"#;
+
+    fn rules(config_name: &str) -> Arc<RulesDatabase> {
+        load(config_name, RULES)
+    }
+
+    /// Loads the rules from a file of the given name, which is removed then
+    fn load(config_name: &str, config: &str) -> Arc<RulesDatabase> {
+        std::fs::write(config_name, config).expect("Cannot create test configuration file");
+        let rules = RulesDatabase::load(
+            std::path::Path::new(config_name), Default::default(), Default::default());
+        std::fs::remove_file(config_name).expect("Cannot remove test configuration file");
+        Arc::new(rules.unwrap())
+    }
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_connections: 4,
+            idle_timeout: None,
+            verify_peer: false,
+        }
+    }
+
+    fn request(request_id: u64, destination: &str, id: u64) -> Request {
+        let mut request = Request::new();
+        request.set_request_id(request_id);
+        request.set_source((&Identifier::from_given("process1", 1)).into());
+        request.set_destination((&Identifier::from_given(destination, id)).into());
+        request
+    }
+
+    fn envelope(request_id: u64, destination: &str) -> RequestEnvelope {
+        let mut envelope = RequestEnvelope::new();
+        envelope.set_single(request(request_id, destination, 2));
+        envelope
+    }
+
+    fn peer(credentials: Option<PeerCredentials>, certificate_name: Option<&str>) -> Peer {
+        Peer {
+            name: "test peer".to_owned(),
+            credentials,
+            certificate_name: certificate_name.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_identity_matches() {
+        let credentials = PeerCredentials { pid: Some(5678), uid: 1000, gid: 1000 };
+        assert_eq!(
+            check_identity(&request(0, "process2", 5678), &peer(Some(credentials), None)), None);
+        assert_eq!(check_identity(&request(0, "process2", 1234), &peer(None, None)), None);
+        assert_eq!(
+            check_identity(&request(0, "process2", 1234), &peer(None, Some("process2"))), None);
+    }
+
+    #[test]
+    fn test_identity_mismatch() {
+        let credentials = PeerCredentials { pid: Some(5678), uid: 1000, gid: 1000 };
+        let mismatch = check_identity(&request(0, "process2", 1234), &peer(Some(credentials), None))
+            .expect("Mismatch should be detected");
+        assert_eq!(mismatch.decision, Decision::IDENTITY_MISMATCH);
+        assert!(mismatch.reason.contains("5678"));
+
+        let credentials = PeerCredentials { pid: None, uid: 1000, gid: 1000 };
+        let mismatch = check_identity(&request(0, "process2", 5678), &peer(Some(credentials), None))
+            .expect("Unknown pid should not be trusted");
+        assert_eq!(mismatch.decision, Decision::IDENTITY_MISMATCH);
+
+        let mismatch = check_identity(&request(0, "process2", 1234), &peer(None, Some("process3")))
+            .expect("Certificate name mismatch should be detected");
+        assert_eq!(mismatch.decision, Decision::IDENTITY_MISMATCH);
+        assert!(mismatch.reason.contains("process3"));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined() {
+        let (sender, receiver) = watch::channel(rules("test_server_pipelined_cfg.json"));
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let connection = tokio::spawn(
+            handle_client(server, peer(None, None), receiver, config()));
+
+        // All the requests are sent before any reply is read
+        let destinations = [(1, "process2"), (2, "process3"), (3, "process2")];
+        for (request_id, destination) in destinations {
+            write_message_async(&mut client, &envelope(request_id, destination), 1024)
+                .await.unwrap();
+        }
+        for (request_id, decision) in
+                [(1, Decision::ALLOW), (2, Decision::DISALLOWED_DESTINATION), (3, Decision::ALLOW)] {
+            let response: ResponseEnvelope = read_message_async(&mut client, 1024).await.unwrap();
+            assert_eq!(response.get_single().get_request_id(), request_id);
+            assert_eq!(response.get_single().get_decision(), decision);
+        }
+
+        // Reloaded rules apply to the connection already open
+        sender.send_replace(load("test_server_reloaded_cfg.json", "[]"));
+        write_message_async(&mut client, &envelope(4, "process2"), 1024).await.unwrap();
+        let response: ResponseEnvelope = read_message_async(&mut client, 1024).await.unwrap();
+        assert_eq!(response.get_single().get_decision(), Decision::SOURCE_UNKNOWN);
+
+        drop(client);
+        connection.await.expect("Connection should be closed by the client");
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let db = rules("test_server_serve_cfg.json");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (_sender, receiver) = watch::channel(db);
+        let config = ServerConfig {
+            max_connections: 1,
+            idle_timeout: Some(Duration::from_millis(500)),
+            ..config()
+        };
+        let server = tokio::spawn(serve(
+            Listener::Tcp(listener), receiver, Arc::new(Semaphore::new(1)), config));
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        write_message_async(&mut first, &envelope(1, "process2"), 1024).await.unwrap();
+        let response: ResponseEnvelope = read_message_async(&mut first, 1024).await.unwrap();
+        assert_eq!(response.get_single().get_decision(), Decision::ALLOW);
+
+        // The second connection waits in the backlog while the first is open
+        let mut second = TcpStream::connect(address).await.unwrap();
+        write_message_async(&mut second, &envelope(2, "process2"), 1024).await.unwrap();
+        let waiting = read_message_async::<ResponseEnvelope, _>(&mut second, 1024);
+        assert!(timeout(Duration::from_millis(200), waiting).await.is_err());
+
+        // Until the first one is closed for being idle
+        match read_message_async::<ResponseEnvelope, _>(&mut first, 1024).await {
+            Err(FrameError::Closed) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let response: ResponseEnvelope = timeout(
+            Duration::from_secs(5), read_message_async(&mut second, 1024))
+            .await.expect("Second connection should be served").unwrap();
+        assert_eq!(response.get_single().get_request_id(), 2);
+        server.abort();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let db = rules("test_server_unix_cfg.json");
+        let path = std::env::temp_dir()
+            .join(format!("osmose_server_test_{}.sock", std::process::id()));
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let (_sender, receiver) = watch::channel(db);
+        let config = ServerConfig { verify_peer: true, ..config() };
+        let server = tokio::spawn(serve(
+            Listener::Unix(listener), receiver, Arc::new(Semaphore::new(1)), config));
+
+        // Claimed ids are checked against the pid of the connected process
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        for (request_id, id, decision) in [
+            (1, u64::from(std::process::id()), Decision::ALLOW),
+            (2, 2, Decision::IDENTITY_MISMATCH),
+        ] {
+            let mut envelope = RequestEnvelope::new();
+            envelope.set_single(request(request_id, "process2", id));
+            write_message_async(&mut stream, &envelope, 1024).await.unwrap();
+            let response: ResponseEnvelope = read_message_async(&mut stream, 1024).await.unwrap();
+            assert_eq!(response.get_single().get_request_id(), request_id);
+            assert_eq!(response.get_single().get_decision(), decision);
+        }
+        server.abort();
+        std::fs::remove_file(&path).expect("Cannot remove test socket");
+    }
+
+    #[test]
+    fn test_batch_envelope() {
+        let db = rules("test_server_batch_cfg.json");
+        let mut batch = BatchRequest::new();
+        batch.set_request_id(10);
+        batch.mut_requests().push(request(13, "process2", 5678));
+        // Identities are checked for every request of the batch on its own
+        batch.mut_requests().push(request(11, "process2", 1234));
+        batch.mut_requests().push(request(12, "process3", 5678));
+        let mut envelope = RequestEnvelope::new();
+        envelope.set_batch(batch);
+
+        let credentials = PeerCredentials { pid: Some(5678), uid: 1000, gid: 1000 };
+        let response = process_envelope(&envelope, &db, &peer(Some(credentials), None));
+        let batch = response.get_batch();
+        assert_eq!(batch.get_request_id(), 10);
+        let replies: Vec<_> = batch.get_responses().iter()
+            .map(|response| (response.get_request_id(), response.get_decision()))
+            .collect();
+        assert_eq!(replies, [
+            (13, Decision::ALLOW),
+            (11, Decision::IDENTITY_MISMATCH),
+            (12, Decision::DISALLOWED_DESTINATION),
+        ]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+
+/// Builds a TLS acceptor from PEM-encoded files
+///
+/// # Arguments
+///
+/// * `cert_path` - Certificate chain of the server
+/// * `key_path` - Private key of the server
+/// * `client_ca_path` - If given, clients have to present a certificate
+///   signed by one of the authorities in this file
+pub fn make_acceptor(
+    cert_path: &Path, key_path: &Path, client_ca_path: Option<&Path>
+) -> std::io::Result<TlsAcceptor> {
+    let certificates = load_certificates(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| invalid_data(key_path, e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(cert_path, e))?;
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(client_ca_path)? {
+                roots.add(certificate).map_err(|e| invalid_data(client_ca_path, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| invalid_data(client_ca_path, e))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certificates, key)
+        .map_err(|e| invalid_data(cert_path, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+
+/// Returns the common name from the subject of the certificate, if any
+pub fn certificate_name(certificate: &CertificateDer) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    let name = certificate.subject().iter_common_name().next()?;
+    name.as_str().ok().map(str::to_owned)
+}
+
+
+fn load_certificates(path: &Path) -> std::io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .map_err(|e| invalid_data(path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid_data(path, e))?;
+    if certificates.is_empty() {
+        return Err(invalid_data(path, "no certificates found"));
+    }
+    Ok(certificates)
+}
+
+
+fn invalid_data<E: std::fmt::Display>(path: &Path, error: E) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), error))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::{watch, Semaphore};
+    use tokio_rustls::TlsConnector;
+
+    use osmose_client::{OsmoseClient, TlsConfig};
+    use osmose_framing::DEFAULT_MAX_FRAME_SIZE;
+    use osmose_generated::generated_proto::osmose::Decision;
+    use osmose_identifier::Identifier;
+
+    use crate::rules_database::RulesDatabase;
+    use crate::server::{serve, Listener, ServerConfig};
+    use crate::tls::{certificate_name, make_acceptor};
+
+    struct Authority {
+        certificate: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    fn make_authority() -> Authority {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "osmose test CA");
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap();
+        Authority { certificate, key }
+    }
+
+    /// Returns PEM-encoded certificate and key signed by the authority
+    fn make_leaf(authority: &Authority, name: &str) -> (String, String) {
+        let mut params = CertificateParams::new(vec![name.to_owned()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        let key = KeyPair::generate().unwrap();
+        let certificate = params
+            .signed_by(&key, &authority.certificate, &authority.key)
+            .unwrap();
+        (certificate.pem(), key.serialize_pem())
+    }
+
+    fn write_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "osmose-tls-{}-{}", std::process::id(), name));
+        std::fs::File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let authority = make_authority();
+        let (server_cert, server_key) = make_leaf(&authority, "localhost");
+        let (client_cert, client_key) = make_leaf(&authority, "process2");
+        let ca_path = write_file("ca.pem", &authority.certificate.pem());
+        let cert_path = write_file("server.pem", &server_cert);
+        let key_path = write_file("server.key", &server_key);
+
+        let acceptor = make_acceptor(&cert_path, &key_path, Some(&ca_path)).unwrap();
+        for path in [&ca_path, &cert_path, &key_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let mut roots = RootCertStore::empty();
+        roots.add(authority.certificate.der().clone()).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_slice(client_cert.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(client_key.as_bytes()).unwrap())
+            .unwrap();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server_stream).await.unwrap();
+            let name = stream.get_ref().1.peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .and_then(certificate_name);
+            let mut data = [0u8; 4];
+            stream.read_exact(&mut data).await.unwrap();
+            (name, data)
+        });
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(server_name, client_stream).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        client.flush().await.unwrap();
+
+        let (name, data) = server.await.unwrap();
+        assert_eq!(name.as_deref(), Some("process2"));
+        assert_eq!(&data, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_required() {
+        let authority = make_authority();
+        let (server_cert, server_key) = make_leaf(&authority, "localhost");
+        let ca_path = write_file("ca-required.pem", &authority.certificate.pem());
+        let cert_path = write_file("server-required.pem", &server_cert);
+        let key_path = write_file("server-required.key", &server_key);
+
+        let acceptor = make_acceptor(&cert_path, &key_path, Some(&ca_path)).unwrap();
+        for path in [&ca_path, &cert_path, &key_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let mut roots = RootCertStore::empty();
+        roots.add(authority.certificate.der().clone()).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+        let server = tokio::spawn(async move {
+            acceptor.accept(server_stream).await.is_ok()
+        });
+        let server_name = ServerName::try_from("localhost").unwrap();
+        // TLS 1.3 clients only learn about the rejection on their first read
+        if let Ok(mut client) = connector.connect(server_name, client_stream).await {
+            let _ = client.read(&mut [0u8; 1]).await;
+        }
+        assert!(!server.await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client() {
+        let authority = make_authority();
+        let (server_cert, server_key) = make_leaf(&authority, "localhost");
+        let (client_cert, client_key) = make_leaf(&authority, "process2");
+        let ca_path = write_file("ca-client.pem", &authority.certificate.pem());
+        let cert_path = write_file("server-client.pem", &server_cert);
+        let key_path = write_file("server-client.key", &server_key);
+        let client_cert_path = write_file("client.pem", &client_cert);
+        let client_key_path = write_file("client.key", &client_key);
+        let rules_path = write_file("rules.json", r#"
+[{"source": {"name": "process1"}, "destinations": [{"name": "process2"}]}]"#);
+
+        let rules = RulesDatabase::load(&rules_path, Default::default(), Default::default());
+        let (_sender, rules) = watch::channel(Arc::new(rules.unwrap()));
+        let config = ServerConfig {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_connections: 4,
+            idle_timeout: None,
+            verify_peer: false,
+        };
+        let mut addresses = Vec::new();
+        for client_ca_path in [None, Some(&ca_path)] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addresses.push(listener.local_addr().unwrap());
+            let listener = Listener::Tls {
+                listener,
+                acceptor: make_acceptor(&cert_path, &key_path, client_ca_path.map(|path| &**path))
+                    .unwrap(),
+                client_auth: client_ca_path.is_some(),
+            };
+            tokio::spawn(serve(listener, rules.clone(), Arc::new(Semaphore::new(4)), config));
+        }
+
+        let ask = move |address, name: &str, tls: TlsConfig| {
+            let mut client = OsmoseClient::from_socket_address(address);
+            client.set_self_id(Identifier::from_given(name, 2));
+            client.set_tls(tls);
+            tokio::task::spawn_blocking(move || {
+                client.ask_for_verdict(&Identifier::from_given("process1", 1), b"test")
+                    .map(|verdict| verdict.get_decision())
+            })
+        };
+        let tls = TlsConfig::new("localhost", &ca_path).unwrap();
+        let mutual = TlsConfig::with_client_certificate(
+            "localhost", &ca_path, &client_cert_path, &client_key_path).unwrap();
+        for path in [&ca_path, &cert_path, &key_path, &client_cert_path, &client_key_path,
+                     &rules_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let decision = ask(addresses[0], "process2", tls.clone()).await.unwrap();
+        assert_eq!(decision.unwrap(), Decision::ALLOW);
+        let decision = ask(addresses[1], "process2", mutual.clone()).await.unwrap();
+        assert_eq!(decision.unwrap(), Decision::ALLOW);
+        // The certificate names the entity asking for the verdict
+        let decision = ask(addresses[1], "process3", mutual).await.unwrap();
+        assert_eq!(decision.unwrap(), Decision::IDENTITY_MISMATCH);
+        // Clients without a certificate cannot ask at all
+        assert!(ask(addresses[1], "process2", tls).await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let missing = std::path::Path::new("/nonexistent/osmose.pem");
+        assert!(make_acceptor(missing, missing, None).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::convert::TryInto;
+
+
+/// Largest field number allowed by the Protobuf wire format
+pub const MAX_FIELD_NUMBER: u64 = (1 << 29) - 1;
+
+
+/// Raw value of a field in Protobuf wire format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+
+impl WireValue<'_> {
+    /// Name of the wire type, for error messages
+    pub fn wire_type(&self) -> &'static str {
+        match self {
+            WireValue::Varint(_) => "varint",
+            WireValue::Fixed64(_) => "64-bit",
+            WireValue::Bytes(_) => "length-delimited",
+            WireValue::Fixed32(_) => "32-bit",
+        }
+    }
+}
+
+
+/// Iterator over the top-level fields of a message in Protobuf wire format
+///
+/// Fields are returned in the order they are encoded, without merging
+/// repeated occurrences of the same field number.
+pub struct WireFields<'a> {
+    data: &'a [u8],
+}
+
+
+impl<'a> WireFields<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        WireFields { data }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for (i, byte) in self.data.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err("payload is not a valid Protobuf message: bad varint".to_owned())
+    }
+
+    fn read_bytes(&mut self, length: u64) -> Result<&'a [u8], String> {
+        if length > self.data.len() as u64 {
+            return Err("payload is not a valid Protobuf message: truncated field".to_owned());
+        }
+        let (bytes, rest) = self.data.split_at(length as usize);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn read_field(&mut self) -> Result<(u32, WireValue<'a>), String> {
+        let key = self.read_varint()?;
+        let number = key >> 3;
+        if number == 0 || number > MAX_FIELD_NUMBER {
+            return Err(format!(
+                "payload is not a valid Protobuf message: bad field number {}", number));
+        }
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(self.read_varint()?),
+            1 => {
+                let bytes = self.read_bytes(8)?;
+                WireValue::Fixed64(u64::from_le_bytes(bytes.try_into().unwrap()))
+            },
+            2 => {
+                let length = self.read_varint()?;
+                WireValue::Bytes(self.read_bytes(length)?)
+            },
+            5 => {
+                let bytes = self.read_bytes(4)?;
+                WireValue::Fixed32(u32::from_le_bytes(bytes.try_into().unwrap()))
+            },
+            wire_type => return Err(format!(
+                "payload is not a valid Protobuf message: unsupported wire type {}",
+                wire_type)),
+        };
+        Ok((number as u32, value))
+    }
+}
+
+
+impl<'a> Iterator for WireFields<'a> {
+    type Item = Result<(u32, WireValue<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.read_field();
+        if field.is_err() {
+            // Nothing after a decoding error can be trusted
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::wire::{WireFields, WireValue};
+
+    #[test]
+    fn test_wire_types() {
+        let data = [
+            0x08, 0x96, 0x01,
+            0x11, 1, 0, 0, 0, 0, 0, 0, 0,
+            0x1a, 0x02, b'h', b'i',
+            0x25, 2, 0, 0, 0,
+        ];
+        let fields: Vec<_> = WireFields::new(&data).collect::<Result<_, _>>().unwrap();
+        assert_eq!(fields, vec![
+            (1, WireValue::Varint(150)),
+            (2, WireValue::Fixed64(1)),
+            (3, WireValue::Bytes(b"hi")),
+            (4, WireValue::Fixed32(2)),
+        ]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for data in [&[0x08][..], &[0x0a, 0x05, 0x00], &[0x00, 0x00], &[0x0b]] {
+            let mut fields = WireFields::new(data);
+            assert!(fields.next().unwrap().is_err(), "{:?}", data);
+            assert!(fields.next().is_none());
+        }
+    }
+}
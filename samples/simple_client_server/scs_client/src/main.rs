@@ -23,13 +23,13 @@ fn main() {
     let msg = String::from("Hello!");
 
     println!("CLIENT: Send request");
-    stream.write(msg.as_bytes()).unwrap();
+    stream.write_all(msg.as_bytes()).unwrap();
 
-    let mut data = [0 as u8; 50];
+    let mut data = [0u8; 50];
     match stream.read(&mut data) {
         Ok(length) => {
             let received_msg = &data[0..length];
-            if str::from_utf8(&received_msg).unwrap() == msg {
+            if str::from_utf8(received_msg).unwrap() == msg {
                 println!("CLIENT: Reply is ok!");
             } else {
                 let text = from_utf8(&data).unwrap();
@@ -31,7 +31,7 @@ fn main() {
 
 fn handle_client(mut stream: TcpStream, self_addr: &str) {
     println!("SERVER: start handle_client");
-    let mut data = [0 as u8; 50];
+    let mut data = [0u8; 50];
     match stream.read(&mut data) {
         Ok(size) => {
             // Use source address as a name
@@ -44,11 +44,21 @@ fn handle_client(mut stream: TcpStream, self_addr: &str) {
             osmose_client.set_self_id(Identifier::from_given(self_addr, 5678));
 
             println!("SERVER: asking OSMOSE");
-            let res = osmose_client.ask_for_verdict(
-                &source_identifier, &payload);
-            println!("SERVER: OSMOSE decided: {}", res);
-            if res {
-                stream.write(&data[0..size]).unwrap();
+            let allowed = match osmose_client.ask_for_verdict(
+                    &source_identifier, payload) {
+                Ok(verdict) => {
+                    println!(
+                        "SERVER: OSMOSE decided: {:?} ({})",
+                        verdict.get_decision(), verdict.get_reason());
+                    verdict.is_allowed()
+                },
+                Err(e) => {
+                    println!("SERVER: OSMOSE request failed: {}", e);
+                    false
+                }
+            };
+            if allowed {
+                stream.write_all(&data[0..size]).unwrap();
             }
 
         },